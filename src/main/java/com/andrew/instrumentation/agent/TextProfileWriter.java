@@ -20,11 +20,34 @@ import java.util.zip.ZipOutputStream;
 
 class TextProfileWriter implements ProfileWriter {
 
+    /** What we know about a thread, written to the "threads" entry */
+    private static class ThreadInfo {
+        final String name;
+        final String group;
+        final boolean daemon;
+        final long firstSeen;
+        volatile long lastSeen;
+
+        ThreadInfo(Thread t, long firstSeen) {
+            this.name = t.getName();
+            ThreadGroup g = t.getThreadGroup();
+            this.group = g == null ? "" : g.getName();
+            this.daemon = t.isDaemon();
+            this.firstSeen = this.lastSeen = firstSeen;
+        }
+    }
+
+    /** Names can contain anything, keep the separators out of them */
+    private static String field(String s) {
+        return s.replace('|', '_').replace('\n', ' ').replace('\r', ' ');
+    }
+
     private ZipOutputStream zipOut;
     private BufferedOutputStream perfWriter;
     private ConcurrentHashMap<Long, Stack<Long>> startStack;
     private ConcurrentHashMap<String, Integer> methodMap;
     private Set<Long> threadIds;
+    private ConcurrentHashMap<Long, ThreadInfo> threadInfo;
     private long duration;
     private AtomicInteger methodIndex;
     private long absStartTime;
@@ -39,6 +62,7 @@ class TextProfileWriter implements ProfileWriter {
             methodMap = new ConcurrentHashMap<>();
             methodIndex = new AtomicInteger(0);
             threadIds = new HashSet<>();
+            threadInfo = new ConcurrentHashMap<>();
             absStartTime = duration = -1L;
         } catch (Exception e) {
             e.printStackTrace();
@@ -63,6 +87,16 @@ class TextProfileWriter implements ProfileWriter {
                     }
                     perfWriter.write(("\n"+duration).getBytes());
 
+                    perfWriter.flush();
+                    zipOut.closeEntry();
+
+                    zipOut.putNextEntry(new ZipEntry("threads"));
+                    for(Map.Entry<Long, ThreadInfo> entry : threadInfo.entrySet()) {
+                        ThreadInfo info = entry.getValue();
+                        perfWriter.write((entry.getKey() + "|" + field(info.name) + "|" + field(info.group) + "|" +
+                                info.daemon + "|" + info.firstSeen + "|" + info.lastSeen + "\n").getBytes());
+                    }
+
                     perfWriter.flush();
                     zipOut.closeEntry();
                     perfWriter.close();
@@ -84,6 +118,7 @@ class TextProfileWriter implements ProfileWriter {
         Stack<Long> currentStartTimes =
                 startStack.computeIfAbsent(currentThread.getId(), (Long l) -> new Stack<>());
         currentStartTimes.push(startTime);
+        threadInfo.computeIfAbsent(currentThread.getId(), (Long l) -> new ThreadInfo(currentThread, startTime-absStartTime));
         methodMap.computeIfAbsent(methodName, (String name) -> methodIndex.getAndIncrement());
     }
 
@@ -104,6 +139,7 @@ class TextProfileWriter implements ProfileWriter {
                     "|" + methodId + "|" + callDepth + "\n");
             perfWriter.write(line.getBytes());
             duration = currentTime-absStartTime;
+            threadInfo.get(tid).lastSeen = duration;
         } catch (Exception e) {
             e.printStackTrace();
         }
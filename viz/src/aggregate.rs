@@ -0,0 +1,257 @@
+use std::cmp::Reverse;
+
+use runic::*;
+use winit::*;
+
+use data::*;
+use menu::*;
+use view::*;
+
+/// One frame of a merged flame graph, standing in for every call that was reached along the same path
+pub struct AggNode {
+    pub method_id: u32,
+    pub depth: u32,
+    /// Offset from the left edge of the root in merged nanoseconds
+    pub start: u64,
+    pub total_time: u64,
+    pub count: u32,
+    pub children: Vec<usize>
+}
+
+/// Call tree built by merging many subtrees together. Node 0 is the root
+pub struct AggTree {
+    pub nodes: Vec<AggNode>
+}
+
+impl AggTree {
+    fn new(method_id: u32) -> AggTree {
+        AggTree {
+            nodes: vec![AggNode { method_id: method_id, depth: 0, start: 0, total_time: 0, count: 0, children: Vec::new() }]
+        }
+    }
+
+    /// Merge every invocation of a method across all threads. Recursive invocations are
+    /// already part of an outer invocation's subtree so only the outermost ones are merged
+    pub fn for_method(data: &VizData, method_id: u32) -> AggTree {
+        let mut t = AggTree::new(method_id);
+        for (i, cr) in data.calls.iter().enumerate() {
+            if cr.method_id != method_id { continue; }
+            if data.ancestors(i).iter().any(|&a| data.calls[a].method_id == method_id) { continue; }
+            t.merge(data, i, 0);
+        }
+        t.layout(0, 0);
+        t
+    }
+
+    fn merge(&mut self, data: &VizData, call: usize, node: usize) {
+        self.nodes[node].total_time += data.calls[call].elapsed_time;
+        self.nodes[node].count += 1;
+        let children = match data.children.get(call) { Some(ch) => ch, None => return };
+        for &c in children.iter() {
+            let method_id = data.calls[c].method_id;
+            let existing = self.nodes[node].children.iter().cloned().find(|&n| self.nodes[n].method_id == method_id);
+            let child = match existing {
+                Some(n) => n,
+                None => {
+                    let n = self.nodes.len();
+                    let depth = self.nodes[node].depth + 1;
+                    self.nodes.push(AggNode { method_id: method_id, depth: depth, start: 0, total_time: 0, count: 0, children: Vec::new() });
+                    self.nodes[node].children.push(n);
+                    n
+                }
+            };
+            self.merge(data, c, child);
+        }
+    }
+
+    /// Place children side by side under their parent, largest first
+    fn layout(&mut self, node: usize, start: u64) {
+        self.nodes[node].start = start;
+        let mut children = self.nodes[node].children.clone();
+        children.sort_by_key(|&n| Reverse(self.nodes[n].total_time));
+        let mut x = start;
+        for &c in children.iter() {
+            self.layout(c, x);
+            x += self.nodes[c].total_time;
+        }
+        self.nodes[node].children = children;
+    }
+
+    pub fn root(&self) -> &AggNode {
+        &self.nodes[0]
+    }
+}
+
+/// Flame graph of every invocation of one method merged together. The x axis is total time, not a timeline
+pub struct MergedFlameGraph {
+    tree: AggTree,
+    offset_x: i64,
+    pixels_per_nanosecond: f32,
+    last_mouse: Point,
+    mouse_state: Option<(MouseButton, Point, i64)>,
+    bounds: Rect,
+    selected_node: Option<usize>,
+    scale: f32,
+}
+
+impl MergedFlameGraph {
+    pub fn new(data: &VizData, method_id: u32, bounds: Rect) -> MergedFlameGraph {
+        MergedFlameGraph {
+            tree: AggTree::for_method(data, method_id),
+            offset_x: 0,
+            pixels_per_nanosecond: 0.0,
+            last_mouse: Point::xy(0.0, 0.0), mouse_state: None,
+            bounds: bounds,
+            selected_node: None,
+            scale: 1.0,
+        }
+    }
+
+    fn node_rect(&self, n: &AggNode) -> Rect {
+        let x = (-self.offset_x + n.start as i64) as f32 * self.pixels_per_nanosecond;
+        Rect::xywh(x, ROW_STRIDE*self.scale*(n.depth+1) as f32, n.total_time as f32 * self.pixels_per_nanosecond, ROW_HEIGHT*self.scale)
+    }
+
+    fn zoom_to(&mut self, node: usize) {
+        let (start, total) = (self.tree.nodes[node].start, self.tree.nodes[node].total_time.max(1));
+        self.pixels_per_nanosecond = (self.bounds.w / total as f32) * 0.9;
+        self.offset_x = start as i64 - (self.bounds.w * 0.05 / self.pixels_per_nanosecond) as i64;
+    }
+}
+
+impl VizView for MergedFlameGraph {
+    fn status(&self, data: &VizData) -> String {
+        let root = self.tree.root();
+        format!("merged {} invocations of {} | {}ns total", root.count,
+                data.method_index.get(&root.method_id).map(|m| m.as_str()).unwrap_or("?"), root.total_time)
+    }
+
+    fn reset(&mut self) {
+        self.pixels_per_nanosecond = 0.0;
+    }
+
+    fn event(&mut self, e: &WindowEvent, _data: &VizData, menus: &mut MenuContext) -> bool {
+        match e {
+            &WindowEvent::KeyboardInput { input: k, .. } => {
+                match k.virtual_keycode {
+                    Some(VirtualKeyCode::Left) => {
+                        self.offset_x -= ((self.bounds.w * 0.1) / self.pixels_per_nanosecond) as i64;
+                    },
+                    Some(VirtualKeyCode::Right) => {
+                        self.offset_x += ((self.bounds.w * 0.1) / self.pixels_per_nanosecond) as i64;
+                    }
+                    Some(VirtualKeyCode::Up) => {
+                        self.pixels_per_nanosecond *= 0.9;
+                    },
+                    Some(VirtualKeyCode::Down) => {
+                        self.pixels_per_nanosecond /= 0.9;
+                    },
+                    _ => {}
+                }
+            },
+            &WindowEvent::CursorMoved { position: (x,y), .. } => {
+                if let Some((MouseButton::Left, click_pos, click_offset)) = self.mouse_state {
+                    self.offset_x = ((click_pos.x - self.last_mouse.x) / self.pixels_per_nanosecond) as i64 + click_offset;
+                }
+                self.last_mouse = Point::xy(x as f32, y as f32);
+            },
+            &WindowEvent::MouseInput{ state, button, .. } => {
+                self.mouse_state = match state {
+                    ElementState::Pressed =>
+                        Some((button, self.last_mouse, self.offset_x)),
+                    _ => None
+                };
+                if state == ElementState::Released && button == MouseButton::Right {
+                    self.selected_node = None;
+                    for (i, n) in self.tree.nodes.iter().enumerate() {
+                        if self.node_rect(n).contains(self.last_mouse) {
+                            menus.popup(vec![ "zoom into view" ], self.last_mouse, "node");
+                            self.selected_node = Some(i);
+                            return true;
+                        }
+                    }
+                }
+            },
+            &WindowEvent::MouseWheel { delta, .. } => {
+                match delta {
+                    MouseScrollDelta::LineDelta(_, y) => {
+                        self.pixels_per_nanosecond += y * 0.00001;
+                    },
+                    MouseScrollDelta::PixelDelta(_, y) => {
+                        self.pixels_per_nanosecond += y * 0.0001;
+                    }
+                }
+            },
+            _ => {}
+        }
+        false
+    }
+
+    fn menu_selection(&mut self, _data: &VizData, tag: &'static str, sel: usize) {
+        if tag == "node" {
+            match (sel, self.selected_node) {
+                (0, Some(n)) => self.zoom_to(n),
+                _ => {}
+            }
+        }
+    }
+
+    fn prompt_result(&mut self, _data: &VizData, _tag: &'static str, _text: &str, _menus: &mut MenuContext) {}
+
+    fn filter_source(&self) -> Option<&str> { None }
+
+    fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData) {
+        self.bounds = rx.bounds();
+        self.scale = res.scale;
+        if self.pixels_per_nanosecond <= 0.0 {
+            self.zoom_to(0);
+        }
+
+        let mut hovered_node: Option<&AggNode> = None;
+        for n in self.tree.nodes.iter() {
+            let r = self.node_rect(n);
+            if r.w < 2.0 { continue; }
+            if r.x+r.w < 0.0 || r.x > self.bounds.w { continue; }
+            let fill = res.color_scheme.block_color(data, &res.theme, n.method_id, None, false);
+            if paint_block(rx, res, r, fill, n.method_id, data, self.last_mouse) {
+                hovered_node = Some(n);
+            }
+        }
+        res.color_scheme.paint_legend(rx, res, data, self.bounds);
+
+        if let Some(n) = hovered_node {
+            let root = self.tree.root();
+            paint_tooltip(rx, res, &format!("{}\nTotal Time: {}, {:.2}% of root\nCalls: {}\nAverage Time: {}",
+                                            data.method_index.get(&n.method_id).unwrap_or(&String::from("?")),
+                                            format_duration(n.total_time), n.total_time as f64 / root.total_time.max(1) as f64 * 100.0,
+                                            n.count, format_duration(n.total_time / n.count.max(1) as u64)),
+                          self.last_mouse, self.bounds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    #[test]
+    fn merge_invocations() {
+        // method 1 runs once on each thread, and calls itself on thread 1
+        let d = profile(&[
+            (1, 15, 5, 3, 3), (1, 10, 30, 2, 2), (1, 55, 5, 3, 3), (1, 50, 40, 1, 2), (1, 0, 100, 1, 1),
+            (2, 210, 20, 2, 2), (2, 230, 20, 4, 2), (2, 200, 60, 1, 1)
+        ]);
+        let t = AggTree::for_method(&d, 1);
+        // the recursive call is part of the outer one's subtree, not merged in again at the root
+        assert_eq!((t.root().count, t.root().total_time), (2, 160));
+        let children: Vec<(u32, u64, u64, u32)> = t.root().children.iter()
+            .map(|&n| (t.nodes[n].method_id, t.nodes[n].start, t.nodes[n].total_time, t.nodes[n].count)).collect();
+        // largest first, side by side
+        assert_eq!(children, vec![(2, 0, 50, 2), (1, 50, 40, 1), (4, 90, 20, 1)]);
+        let recursive = &t.nodes[t.root().children[1]];
+        assert_eq!(recursive.depth, 1);
+        let grandchild = &t.nodes[recursive.children[0]];
+        assert_eq!((grandchild.method_id, grandchild.depth, grandchild.start, grandchild.total_time), (3, 2, 50, 5));
+    }
+}
@@ -0,0 +1,174 @@
+use runic::*;
+
+use data::*;
+use view::Resources;
+use names::stable_hash;
+use theme::*;
+
+pub type Rgb = (f32, f32, f32);
+
+/// Ways of choosing the fill color of a block
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorScheme {
+    MethodHash,
+    Package,
+    SelfTimeHeat,
+    RelativeToMedian,
+    Thread,
+    SearchMatch
+}
+
+const ALL_SCHEMES: [ColorScheme; 6] = [
+    ColorScheme::MethodHash, ColorScheme::Package, ColorScheme::SelfTimeHeat,
+    ColorScheme::RelativeToMedian, ColorScheme::Thread, ColorScheme::SearchMatch
+];
+
+/// Number of package segments that identify a package for coloring
+const PACKAGE_DEPTH: usize = 2;
+
+/// Hue in [0,1) to a fully saturated but not too bright color, so black labels stay readable
+fn hue(h: f32) -> Rgb {
+    let h = (h.fract() + 1.0).fract() * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x)
+    };
+    (0.35 + r * 0.55, 0.35 + g * 0.55, 0.35 + b * 0.55)
+}
+
+fn hash_hue(key: u64) -> Rgb {
+    hue((key % 4096) as f32 / 4096.0)
+}
+
+/// Blue for 0 through yellow to red for 1
+fn heat(t: f32) -> Rgb {
+    let t = t.max(0.0).min(1.0);
+    if t < 0.5 {
+        let u = t * 2.0;
+        (0.3 + 0.6 * u, 0.4 + 0.5 * u, 0.9 - 0.6 * u)
+    } else {
+        let u = (t - 0.5) * 2.0;
+        (0.9, 0.9 - 0.7 * u, 0.3 - 0.2 * u)
+    }
+}
+
+/// Green when faster than typical, grey around typical, red when slower. `t` is log2 of the ratio to the median
+fn relative(t: f32) -> Rgb {
+    let t = t.max(-2.0).min(2.0) / 2.0;
+    if t < 0.0 {
+        (0.6 + 0.2 * t, 0.6 - 0.2 * t, 0.6 + 0.2 * t)
+    } else {
+        (0.6 + 0.35 * t, 0.6 - 0.4 * t, 0.6 - 0.4 * t)
+    }
+}
+
+fn package_key(data: &VizData, method_id: u32) -> String {
+    data.method_names.get(&method_id).map(|m| m.package_prefix(PACKAGE_DEPTH))
+        .unwrap_or_else(|| data.method_index.get(&method_id).cloned().unwrap_or_default())
+}
+
+impl ColorScheme {
+    pub fn all() -> &'static [ColorScheme] {
+        &ALL_SCHEMES
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColorScheme::MethodHash => "color by method",
+            ColorScheme::Package => "color by package",
+            ColorScheme::SelfTimeHeat => "color by self time",
+            ColorScheme::RelativeToMedian => "color by duration vs. median",
+            ColorScheme::Thread => "color by thread",
+            ColorScheme::SearchMatch => "color by filter match"
+        }
+    }
+
+    /// Fill color of a block. Blocks that don't stand for a single call (like merged frames) pass `None` for
+    /// the call, and fall back to coloring by method for schemes that need one
+    pub fn block_color(&self, data: &VizData, theme: &Theme, method_id: u32, call: Option<usize>, matched: bool) -> Rgb {
+        let by_method = || if theme.block_palette.len() > 0 {
+            theme.block_palette[(data.method_key(method_id) % theme.block_palette.len() as u64) as usize]
+        } else {
+            (0.8, 0.6, (data.method_key(method_id) % 1024) as f32 / 1023.0)
+        };
+        match (*self, call) {
+            (ColorScheme::MethodHash, _) => by_method(),
+            (ColorScheme::Package, _) => hash_hue(stable_hash(&package_key(data, method_id))),
+            (ColorScheme::SelfTimeHeat, Some(i)) => match data.self_times.get(i) {
+                Some(&st) => heat(((1 + st) as f32).ln() / ((1 + data.max_self_time) as f32).ln().max(1.0)),
+                None => by_method()
+            },
+            (ColorScheme::RelativeToMedian, Some(i)) => match data.method_stats.get(&method_id) {
+                Some(s) if s.median_time > 0 => relative((data.calls[i].elapsed_time as f32 / s.median_time as f32).log2()),
+                _ => by_method()
+            },
+            (ColorScheme::Thread, Some(i)) => hash_hue(stable_hash(&data.calls[i].thread_id.to_string())),
+            (ColorScheme::SearchMatch, Some(_)) => if matched { (1.0, 0.6, 0.1) } else { (0.45, 0.45, 0.45) },
+            (_, None) => by_method()
+        }
+    }
+
+    /// Swatches and labels explaining the colors
+    pub fn legend(&self, data: &VizData) -> Vec<(Rgb, String)> {
+        match *self {
+            ColorScheme::MethodHash => vec![
+                ((0.8, 0.6, 0.0), String::from("each method has its own shade,")),
+                ((0.8, 0.6, 1.0), String::from("the same in every profile"))
+            ],
+            ColorScheme::Package => {
+                let mut packages: Vec<String> = data.method_index.keys().map(|&id| package_key(data, id)).collect();
+                packages.sort();
+                packages.dedup();
+                let more = packages.len().saturating_sub(8);
+                let mut l: Vec<(Rgb, String)> = packages.into_iter().take(8).map(|p| (hash_hue(stable_hash(&p)), p)).collect();
+                if more > 0 { l.push(((0.3, 0.3, 0.3), format!("... {} more", more))); }
+                l
+            },
+            ColorScheme::SelfTimeHeat => vec![
+                (heat(0.0), String::from("no self time")),
+                (heat(0.5), format!("~{}ns self time", ((1 + data.max_self_time) as f32).sqrt() as u64)),
+                (heat(1.0), format!("{}ns self time", data.max_self_time))
+            ],
+            ColorScheme::RelativeToMedian => vec![
+                (relative(-2.0), String::from("4x faster than median")),
+                (relative(0.0), String::from("median")),
+                (relative(2.0), String::from("4x slower than median"))
+            ],
+            ColorScheme::Thread => {
+                let mut l: Vec<(Rgb, String)> = data.thread_ids.iter().take(8)
+                    .map(|&t| (hash_hue(stable_hash(&t.to_string())), data.thread_name(t))).collect();
+                if data.thread_ids.len() > 8 { l.push(((0.3, 0.3, 0.3), format!("... {} more", data.thread_ids.len() - 8))); }
+                l
+            },
+            ColorScheme::SearchMatch => vec![
+                ((1.0, 0.6, 0.1), String::from("matches filter")),
+                ((0.45, 0.45, 0.45), String::from("does not match"))
+            ]
+        }
+    }
+
+    /// Draw the legend in the bottom right corner of `area`
+    pub fn paint_legend(&self, rx: &mut RenderContext, res: &Resources, data: &VizData, area: Rect) {
+        let entries = self.legend(data);
+        let line_h = res.px(20.0);
+        let w = entries.iter().map(|&(_, ref s)| s.chars().count()).max().unwrap_or(0) as f32 * res.char_width + res.px(32.0);
+        let h = entries.len() as f32 * line_h + res.px(8.0);
+        let bnd = Rect::xywh(area.x + area.w - w - res.px(8.0), area.y + area.h - h - res.px(8.0), w, h);
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(bnd);
+        rx.set_color(to_color(res.theme.panel_border));
+        rx.stroke_rect(bnd, res.px(1.0));
+        for (i, &((r, g, b), ref label)) in entries.iter().enumerate() {
+            let y = bnd.y + res.px(4.0) + i as f32 * line_h;
+            rx.set_color(Color::rgb(r, g, b));
+            rx.fill_rect(Rect::xywh(bnd.x + res.px(6.0), y + res.px(3.0), res.px(14.0), res.px(14.0)));
+            rx.set_color(to_color(res.theme.panel_text));
+            rx.draw_text(Rect::xywh(bnd.x + res.px(26.0), y, w - res.px(28.0), line_h), label, &res.font);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use runic::*;
+use winit::*;
+
+use data::*;
+use menu::*;
+use view::*;
+use theme::*;
+
+/// Number of threads inside at least one instrumented call at every point in the profile. Each step starts at a
+/// time and holds until the next one
+pub fn active_threads(data: &VizData) -> Vec<(u64, u32)> {
+    // outermost calls of a thread never overlap, so each one covering a time is one active thread
+    let mut events: Vec<(u64, i32)> = Vec::new();
+    for (i, cr) in data.calls.iter().enumerate() {
+        if data.parents.get(i).map(|p| p.is_some()).unwrap_or(false) { continue; }
+        events.push((cr.start_time, 1));
+        events.push((cr.start_time + cr.elapsed_time, -1));
+    }
+    // ends sort before starts at the same time so back to back calls don't count twice
+    events.sort();
+    let mut steps: Vec<(u64, u32)> = vec![(0, 0)];
+    let mut active = 0i32;
+    for (t, d) in events {
+        active += d;
+        if steps.last().map(|&(lt, _)| lt == t).unwrap_or(false) {
+            steps.last_mut().unwrap().1 = active.max(0) as u32;
+        } else {
+            steps.push((t, active.max(0) as u32));
+        }
+    }
+    steps
+}
+
+/// Graph of how many threads were busy over time, for finding serialization points and idle phases
+pub struct ConcurrencyView {
+    steps: Vec<(u64, u32)>,
+    max_active: u32,
+    offset_x: i64,
+    pixels_per_nanosecond: f32,
+    last_mouse: Point,
+    mouse_state: Option<(MouseButton, Point, i64)>,
+    bounds: Rect
+}
+
+impl ConcurrencyView {
+    pub fn new(data: &VizData) -> ConcurrencyView {
+        let steps = active_threads(data);
+        ConcurrencyView {
+            max_active: steps.iter().map(|&(_, c)| c).max().unwrap_or(0),
+            steps: steps,
+            offset_x: 0,
+            pixels_per_nanosecond: 0.0,
+            last_mouse: Point::xy(0.0, 0.0), mouse_state: None,
+            bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0)
+        }
+    }
+
+    fn time_at(&self, x: f32) -> i64 {
+        self.offset_x + (x / self.pixels_per_nanosecond) as i64
+    }
+
+    /// Number of active threads at a time
+    fn active_at(&self, t: i64) -> u32 {
+        if t < 0 { return 0; }
+        match self.steps.binary_search_by_key(&(t as u64), |&(st, _)| st) {
+            Ok(i) => self.steps[i].1,
+            Err(0) => 0,
+            Err(i) => self.steps[i - 1].1
+        }
+    }
+}
+
+impl VizView for ConcurrencyView {
+    fn status(&self, _data: &VizData) -> String {
+        format!("active threads over time | at most {} at once", self.max_active)
+    }
+
+    fn reset(&mut self) {
+        self.offset_x = 0;
+        self.pixels_per_nanosecond = 0.0;
+    }
+
+    fn event(&mut self, e: &WindowEvent, _data: &VizData, _menus: &mut MenuContext) -> bool {
+        match e {
+            &WindowEvent::KeyboardInput { input: k, .. } => {
+                match k.virtual_keycode {
+                    Some(VirtualKeyCode::Left) => {
+                        self.offset_x -= ((self.bounds.w * 0.1) / self.pixels_per_nanosecond) as i64;
+                    },
+                    Some(VirtualKeyCode::Right) => {
+                        self.offset_x += ((self.bounds.w * 0.1) / self.pixels_per_nanosecond) as i64;
+                    }
+                    Some(VirtualKeyCode::Up) => {
+                        self.pixels_per_nanosecond *= 0.9;
+                    },
+                    Some(VirtualKeyCode::Down) => {
+                        self.pixels_per_nanosecond /= 0.9;
+                    },
+                    _ => {}
+                }
+            },
+            &WindowEvent::CursorMoved { position: (x,y), .. } => {
+                if let Some((MouseButton::Left, click_pos, click_offset)) = self.mouse_state {
+                    self.offset_x = ((click_pos.x - self.last_mouse.x) / self.pixels_per_nanosecond) as i64 + click_offset;
+                }
+                self.last_mouse = Point::xy(x as f32, y as f32);
+            },
+            &WindowEvent::MouseInput{ state, button, .. } => {
+                self.mouse_state = match state {
+                    ElementState::Pressed =>
+                        Some((button, self.last_mouse, self.offset_x)),
+                    _ => None
+                };
+            },
+            &WindowEvent::MouseWheel { delta, .. } => {
+                // zoom around the mouse so the time under it stays put
+                let t = self.time_at(self.last_mouse.x);
+                match delta {
+                    MouseScrollDelta::LineDelta(_, y) => {
+                        self.pixels_per_nanosecond *= 1.1f32.powf(y);
+                    },
+                    MouseScrollDelta::PixelDelta(_, y) => {
+                        self.pixels_per_nanosecond *= 1.01f32.powf(y);
+                    }
+                }
+                self.offset_x = t - (self.last_mouse.x / self.pixels_per_nanosecond) as i64;
+            },
+            _ => {}
+        }
+        false
+    }
+
+    fn menu_selection(&mut self, _data: &VizData, _tag: &'static str, _sel: usize) {}
+
+    fn prompt_result(&mut self, _data: &VizData, _tag: &'static str, _text: &str, _menus: &mut MenuContext) {}
+
+    fn filter_source(&self) -> Option<&str> { None }
+
+    fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData) {
+        self.bounds = rx.bounds();
+        if self.pixels_per_nanosecond <= 0.0 {
+            self.pixels_per_nanosecond = self.bounds.w / data.abs_end_time.max(1) as f32;
+        }
+        let top = res.line_height + res.px(8.0);
+        let graph = Rect::xywh(0.0, top, self.bounds.w, self.bounds.h - top - res.line_height - res.px(8.0));
+        let max = self.max_active.max(1) as f32;
+
+        // grid line for every thread count, or every few when there are many
+        let every = ((max / 8.0).ceil() as u32).max(1);
+        let mut n = every;
+        while n <= self.max_active {
+            let y = graph.y + graph.h - n as f32 / max * graph.h;
+            rx.set_color(to_color(res.theme.panel_background));
+            rx.draw_line(Point::xy(0.0, y), Point::xy(graph.w, y), res.px(1.0));
+            rx.set_color(to_color(res.theme.text));
+            rx.draw_text(Rect::xywh(res.px(2.0), y - res.line_height, res.px(64.0), res.line_height), &n.to_string(), &res.font);
+            n += every;
+        }
+
+        // each column shows the average over the time it covers, with the peak drawn on top
+        let columns = self.bounds.w.max(1.0) as usize;
+        let ns_per_column = (1.0 / self.pixels_per_nanosecond) as f64;
+        let mut avg = vec![0.0f64; columns];
+        let mut peak = vec![0u32; columns];
+        let start = self.offset_x as f64;
+        for (i, &(t, c)) in self.steps.iter().enumerate() {
+            let end = self.steps.get(i + 1).map(|&(nt, _)| nt).unwrap_or(data.abs_end_time.max(t)) as f64;
+            let (x0, x1) = ((t as f64 - start) / ns_per_column, (end - start) / ns_per_column);
+            if x1 < 0.0 || x0 >= columns as f64 { continue; }
+            let (c0, c1) = (x0.max(0.0) as usize, (x1.ceil() as usize).min(columns));
+            for col in c0..c1.max(c0 + 1).min(columns) {
+                let overlap = x1.min(col as f64 + 1.0) - x0.max(col as f64);
+                if overlap > 0.0 { avg[col] += overlap * c as f64; }
+                peak[col] = peak[col].max(c);
+            }
+        }
+        rx.set_color(to_color(res.theme.panel_highlight));
+        for col in 0..columns {
+            let h = peak[col] as f32 / max * graph.h;
+            if h > 0.0 { rx.fill_rect(Rect::xywh(col as f32, graph.y + graph.h - h, 1.0, h)); }
+        }
+        rx.set_color(to_color(res.theme.panel_border));
+        for col in 0..columns {
+            let h = avg[col] as f32 / max * graph.h;
+            if h > 0.0 { rx.fill_rect(Rect::xywh(col as f32, graph.y + graph.h - h, 1.0, h)); }
+        }
+
+        if graph.contains(self.last_mouse) {
+            let t = self.time_at(self.last_mouse.x);
+            rx.set_color(to_color(res.theme.text));
+            rx.draw_line(Point::xy(self.last_mouse.x, graph.y), Point::xy(self.last_mouse.x, graph.y + graph.h), res.px(1.0));
+            let col = (self.last_mouse.x.max(0.0) as usize).min(columns - 1);
+            paint_tooltip(rx, res, &format!("t = {}\n{} active threads\naverage {:.1}, peak {} over this pixel",
+                                            format_duration(t.max(0) as u64), self.active_at(t), avg[col], peak[col]),
+                          self.last_mouse, self.bounds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    #[test]
+    fn active_threads_over_time() {
+        // thread 2 starts as thread 1's first call ends, and nested calls don't add a thread
+        let d = profile(&[(1, 2, 3, 2, 2), (1, 0, 10, 1, 1), (2, 10, 10, 1, 1), (1, 15, 10, 1, 1)]);
+        assert_eq!(active_threads(&d), vec![(0, 1), (10, 1), (15, 2), (20, 1), (25, 0)]);
+    }
+}
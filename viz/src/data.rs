@@ -1,5 +1,5 @@
 use std::io;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Seek};
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::iter::FromIterator;
@@ -8,6 +8,9 @@ use std::path::{Path,PathBuf};
 use std::error::Error;
 
 use zip::read::*;
+use zip::result::ZipError;
+
+use names::{MethodName, Normalizer, stable_hash};
 
 #[derive(Debug, Copy, Clone)]
 /// Times in nanoseconds
@@ -64,13 +67,103 @@ fn read_method_index<R: BufRead>(data: R) -> Result<HashMap<u32, String>, io::Er
     Ok(ix)
 }
 
+/// Nanoseconds in the largest unit that keeps the number readable
+pub fn format_duration(ns: u64) -> String {
+    if ns >= 1_000_000_000 {
+        format!("{:.2}s", ns as f64 / 1e9)
+    } else if ns >= 1_000_000 {
+        format!("{:.2}ms", ns as f64 / 1e6)
+    } else if ns >= 1_000 {
+        format!("{:.2}us", ns as f64 / 1e3)
+    } else {
+        format!("{}ns", ns)
+    }
+}
+
+/// A thread as described by the agent's thread table
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: u32,
+    pub name: String,
+    pub group: String,
+    pub daemon: bool,
+    /// Times the thread first entered and last left an instrumented method
+    pub first_seen: u64,
+    pub last_seen: u64
+}
+
+fn read_thread_table<R: BufRead>(data: R) -> Result<HashMap<u32, ThreadInfo>, io::Error> {
+    let mut threads = HashMap::new();
+    for linep in data.lines() {
+        let line = linep?;
+        if line.len() == 0 { continue; }
+        let items: Vec<&str> = line.split('|').collect();
+        if items.len() != 6 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected 6 fields in thread table, got '{}'", line)));
+        }
+        let id = items[0].parse::<u32>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        threads.insert(id, ThreadInfo {
+            id: id,
+            name: String::from(items[1]),
+            group: String::from(items[2]),
+            daemon: items[3] == "true",
+            first_seen: items[4].parse::<u64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            last_seen: items[5].parse::<u64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        });
+    }
+    Ok(threads)
+}
+
+/// The archive's thread table, empty for older archives that have none
+fn read_threads<R: Read + Seek>(ach: &mut ZipArchive<R>) -> Result<HashMap<u32, ThreadInfo>, io::Error> {
+    match ach.by_name("threads") {
+        Ok(f) => read_thread_table(BufReader::new(f)),
+        Err(ZipError::FileNotFound) => Ok(HashMap::new()),
+        Err(e) => Err(From::from(e))
+    }
+}
+
+/// Summary of every call to one method
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MethodStats {
+    pub count: usize,
+    pub total_time: u64,
+    pub self_time: u64,
+    pub median_time: u64,
+    pub max_time: u64
+}
+
 pub struct VizData {
     pub calls: Vec<CallRecord>,
+    /// Index of the enclosing call for each call, built once loading finishes
+    pub parents: Vec<Option<usize>>,
+    /// Indices of the calls made directly by each call, in start order
+    pub children: Vec<Vec<usize>>,
+    /// Calls with no parent, ordered by thread then start time
+    pub roots: Vec<usize>,
+    /// Number of recursive calls folded into each call, empty unless recursion has been folded
+    pub recursion_depth: Vec<u32>,
+    /// Time spent in each call outside of the calls it made
+    pub self_times: Vec<u64>,
+    pub method_stats: HashMap<u32, MethodStats>,
+    /// Every call of each method in start order
+    pub invocations: HashMap<u32, Vec<usize>>,
+    /// Position of each call in its method's invocations
+    pub invocation_index: Vec<usize>,
+    /// Time each thread spent inside its outermost calls
+    pub thread_busy_time: HashMap<u32, u64>,
+    pub max_self_time: u64,
     pub method_index: HashMap<u32, String>,
+    /// Parsed form of every name in `method_index`
+    pub method_names: HashMap<u32, MethodName>,
     pub thread_ids: Vec<u32>,
+    /// Names and details of threads, empty for archives written before the agent recorded them
+    pub threads: HashMap<u32, ThreadInfo>,
     pub abs_end_time: u64,
     pub path: Option<PathBuf>,
-    pub loaded: bool
+    pub loaded: bool,
+    /// Problems found while loading, for the interface to show
+    pub errors: Vec<String>
 }
 
 fn flatten_opt_res<T,E,F: FnOnce()->E>(v: Option<Result<T,E>>, none_err: F) -> Result<T, E> {
@@ -84,11 +177,24 @@ impl Default for VizData {
     fn default() -> VizData {
         VizData {
             calls: Vec::new(),
+            parents: Vec::new(),
+            children: Vec::new(),
+            roots: Vec::new(),
+            recursion_depth: Vec::new(),
+            self_times: Vec::new(),
+            method_stats: HashMap::new(),
+            invocations: HashMap::new(),
+            invocation_index: Vec::new(),
+            thread_busy_time: HashMap::new(),
+            max_self_time: 0,
             method_index: HashMap::new(),
+            method_names: HashMap::new(),
             thread_ids: Vec::new(),
+            threads: HashMap::new(),
             abs_end_time: 0,
             path: None,
-            loaded: true
+            loaded: true,
+            errors: Vec::new()
         }
     }
 }
@@ -99,25 +205,36 @@ impl VizData {
         let mut dp = PathBuf::new(); dp.push(data_path);
         VizData {
             calls: Vec::new(),
+            parents: Vec::new(),
+            children: Vec::new(),
+            roots: Vec::new(),
+            recursion_depth: Vec::new(),
+            self_times: Vec::new(),
+            method_stats: HashMap::new(),
+            invocations: HashMap::new(),
+            invocation_index: Vec::new(),
+            thread_busy_time: HashMap::new(),
+            max_self_time: 0,
             method_index: HashMap::new(),
+            method_names: HashMap::new(),
             thread_ids: Vec::new(),
+            threads: HashMap::new(),
             abs_end_time: 0,
             path: Some(dp),
-            loaded: false
+            loaded: false,
+            errors: Vec::new()
         }
     }
 
     /// Load data from files if it is unloaded
     pub fn load(data: ::std::sync::Arc<::std::sync::RwLock<VizData>>) -> Result<(), io::Error> {
-        let mut ach = {
-            let s = data.read().unwrap();
-            ZipArchive::new(File::open(&s.path.as_ref().expect("data not associated with path"))?)?
-        };
+        let path = data.read().unwrap().path.clone().expect("data not associated with path");
+        let mut ach = ZipArchive::new(File::open(&path)?)?;
         {
             let mut header_f = (BufReader::new(ach.by_name("header")?)).lines();
             let mut vd = data.write().unwrap();
             vd.loaded = false;
-            vd.calls.clear(); vd.method_index.clear();
+            vd.calls.clear(); vd.parents.clear(); vd.children.clear(); vd.roots.clear(); vd.recursion_depth.clear(); vd.self_times.clear(); vd.method_stats.clear(); vd.invocations.clear(); vd.invocation_index.clear(); vd.thread_busy_time.clear(); vd.method_index.clear(); vd.method_names.clear(); vd.errors.clear();
             vd.thread_ids = Vec::new();
             for id in flatten_opt_res(header_f.next(), ||io::Error::new(io::ErrorKind::UnexpectedEof, ""))?.split(';').filter(|x| x.len()!=0).map(|v| v.parse::<u32>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))) {
                     
@@ -125,12 +242,27 @@ impl VizData {
             }
             vd.abs_end_time = flatten_opt_res(header_f.next(), ||io::Error::new(io::ErrorKind::UnexpectedEof, ""))?.parse::<u64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         }
-        {(data.write().unwrap().method_index) = read_method_index(BufReader::new(ach.by_name("methods")?))?; }
+        {
+            let threads = read_threads(&mut ach)?;
+            data.write().unwrap().threads = threads;
+        }
+        let method_remap = {
+            let (normalizer, mut errors) = Normalizer::for_profile(&path)?;
+            let (method_index, remap) = normalizer.apply(read_method_index(BufReader::new(ach.by_name("methods")?))?);
+            let mut vd = data.write().unwrap();
+            vd.errors.append(&mut errors);
+            vd.method_names = method_index.iter().map(|(&id, name)| (id, MethodName::parse(name))).collect();
+            vd.method_index = method_index;
+            remap
+        };
         let mut res = Vec::new();
         {
             let data_f = BufReader::new(ach.by_name("data")?);
             for linep in data_f.lines().skip(1) {
-                let cr = CallRecord::from_psv(&linep?)?;
+                let mut cr = CallRecord::from_psv(&linep?)?;
+                if let Some(&id) = method_remap.get(&cr.method_id) {
+                    cr.method_id = id;
+                }
                 res.push(cr);
                 if res.len() > 16 {
                     data.write().unwrap().calls.append(&mut res);
@@ -138,8 +270,260 @@ impl VizData {
             }
             data.write().unwrap().calls.append(&mut res);
         }
-        data.write().unwrap().loaded = true;
+        {
+            let mut vd = data.write().unwrap();
+            vd.build_indices();
+            vd.loaded = true;
+        }
         Ok(())
     }
+
+    /// Load on a background thread. A failed load keeps whatever was read before the error and records it in
+    /// `errors`, so the interface doesn't wait for the rest forever
+    pub fn load_in_background(data: ::std::sync::Arc<::std::sync::RwLock<VizData>>) {
+        ::std::thread::spawn(move || {
+            if let Err(e) = VizData::load(data.clone()) {
+                let mut vd = data.write().unwrap();
+                let msg = format!("could not load {}: {}", vd.path.as_ref().map(|p| p.display().to_string()).unwrap_or_default(), e);
+                vd.errors.push(msg);
+                vd.build_indices();
+                vd.loaded = true;
+            }
+        });
+    }
+
+    /// Name to show for a thread, its id if the archive has no name for it
+    pub fn thread_name(&self, thread_id: u32) -> String {
+        match self.threads.get(&thread_id) {
+            Some(t) if t.name.len() > 0 => t.name.clone(),
+            _ => format!("Thread #{}", thread_id)
+        }
+    }
+
+    /// Build everything derived from the calls once they are all loaded
+    pub fn build_indices(&mut self) {
+        self.build_call_tree();
+        self.build_method_stats();
+    }
+
+    fn build_method_stats(&mut self) {
+        self.self_times = self.calls.iter().zip(self.children.iter())
+            .map(|(cr, ch)| cr.elapsed_time.saturating_sub(ch.iter().map(|&c| self.calls[c].elapsed_time).sum())).collect();
+        self.max_self_time = self.self_times.iter().cloned().max().unwrap_or(0);
+        let mut durations: HashMap<u32, Vec<u64>> = HashMap::new();
+        let mut stats: HashMap<u32, MethodStats> = HashMap::new();
+        for (i, cr) in self.calls.iter().enumerate() {
+            durations.entry(cr.method_id).or_insert_with(Vec::new).push(cr.elapsed_time);
+            let s = stats.entry(cr.method_id).or_insert_with(MethodStats::default);
+            s.count += 1;
+            s.total_time += cr.elapsed_time;
+            s.self_time += self.self_times[i];
+            s.max_time = s.max_time.max(cr.elapsed_time);
+        }
+        for (id, mut ds) in durations.into_iter() {
+            ds.sort();
+            stats.get_mut(&id).unwrap().median_time = ds[ds.len() / 2];
+        }
+        self.method_stats = stats;
+
+        let mut invocations: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, cr) in self.calls.iter().enumerate() {
+            invocations.entry(cr.method_id).or_insert_with(Vec::new).push(i);
+        }
+        let mut invocation_index = vec![0; self.calls.len()];
+        for calls in invocations.values_mut() {
+            {
+                let all = &self.calls;
+                calls.sort_by_key(|&i| (all[i].start_time, all[i].thread_id, all[i].depth));
+            }
+            for (n, &i) in calls.iter().enumerate() {
+                invocation_index[i] = n;
+            }
+        }
+        self.invocations = invocations;
+        self.invocation_index = invocation_index;
+
+        let mut busy: HashMap<u32, u64> = HashMap::new();
+        for &r in self.roots.iter() {
+            *busy.entry(self.calls[r].thread_id).or_insert(0) += self.calls[r].elapsed_time;
+        }
+        self.thread_busy_time = busy;
+    }
+
+    /// Find the parent of every call. Records are written as calls exit, so walk each thread in start order
+    /// keeping a stack of the calls that are still open
+    fn build_call_tree(&mut self) {
+        let mut order: Vec<usize> = (0..self.calls.len()).collect();
+        {
+            let calls = &self.calls;
+            order.sort_by_key(|&i| (calls[i].thread_id, calls[i].start_time, calls[i].depth));
+        }
+        let mut parents = vec![None; self.calls.len()];
+        let mut children = vec![Vec::new(); self.calls.len()];
+        let mut roots = Vec::new();
+        let mut stack: Vec<usize> = Vec::new();
+        let mut thread = None;
+        for &i in order.iter() {
+            let cr = self.calls[i];
+            if thread != Some(cr.thread_id) {
+                stack.clear();
+                thread = Some(cr.thread_id);
+            }
+            while stack.last().map(|&p| self.calls[p].depth >= cr.depth).unwrap_or(false) {
+                stack.pop();
+            }
+            parents[i] = stack.last().cloned();
+            match stack.last() {
+                Some(&p) => children[p].push(i),
+                None => roots.push(i)
+            }
+            stack.push(i);
+        }
+        self.parents = parents;
+        self.children = children;
+        self.roots = roots;
+    }
+
+    /// The stack of every thread that was inside a call at a point in time, outermost call first
+    pub fn stacks_at(&self, time: u64) -> Vec<(u32, Vec<usize>)> {
+        let covers = |i: usize| self.calls[i].start_time <= time && time < self.calls[i].start_time + self.calls[i].elapsed_time;
+        let mut stacks = Vec::new();
+        for &r in self.roots.iter() {
+            if !covers(r) { continue; }
+            let mut stack = vec![r];
+            while let Some(&c) = self.children[*stack.last().unwrap()].iter().find(|&&c| covers(c)) {
+                stack.push(c);
+            }
+            stacks.push((self.calls[r].thread_id, stack));
+        }
+        stacks
+    }
+
+    /// Identity of a method that is stable between captures, from the hash of its normalized name
+    pub fn method_key(&self, method_id: u32) -> u64 {
+        self.method_index.get(&method_id).map(|m| stable_hash(m)).unwrap_or(method_id as u64)
+    }
+
+    /// Indices of the calls enclosing a call, outermost first
+    pub fn ancestors(&self, call: usize) -> Vec<usize> {
+        let mut res = Vec::new();
+        let mut cur = self.parents.get(call).cloned().unwrap_or(None);
+        while let Some(p) = cur {
+            res.push(p);
+            cur = self.parents[p];
+        }
+        res.reverse();
+        res
+    }
+
+    /// Check if a call lies inside another call's subtree, including the call itself
+    pub fn is_within(&self, call: usize, root: usize) -> bool {
+        let (cr, rt) = (&self.calls[call], &self.calls[root]);
+        cr.thread_id == rt.thread_id && cr.depth >= rt.depth &&
+            cr.start_time >= rt.start_time && cr.start_time + cr.elapsed_time <= rt.start_time + rt.elapsed_time
+    }
 }
 
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Loaded data from `(thread, start, elapsed, method, depth)` records, in the order the agent writes them
+    pub fn profile(records: &[(u32, u64, u64, u32, u32)]) -> VizData {
+        let mut d = VizData::default();
+        d.calls = records.iter().map(|&(t, s, e, m, depth)| CallRecord { thread_id: t, start_time: s, elapsed_time: e, method_id: m, depth: depth }).collect();
+        for cr in d.calls.iter() {
+            if !d.thread_ids.contains(&cr.thread_id) { d.thread_ids.push(cr.thread_id); }
+            d.abs_end_time = d.abs_end_time.max(cr.start_time + cr.elapsed_time);
+        }
+        d.build_indices();
+        d
+    }
+
+    /// Two threads, the first with a call that makes two calls to the same method
+    fn sample() -> VizData {
+        profile(&[
+            (1, 15, 5, 3, 3),
+            (1, 10, 30, 2, 2),
+            (1, 50, 40, 2, 2),
+            (1, 0, 100, 1, 1),
+            (2, 5, 55, 1, 1)
+        ])
+    }
+
+    /// An archive holding the given entries
+    fn archive(entries: &[(&str, &str)]) -> ZipArchive<io::Cursor<Vec<u8>>> {
+        let mut w = ::zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for &(name, contents) in entries.iter() {
+            w.start_file(name, ::zip::write::FileOptions::default()).unwrap();
+            io::Write::write_all(&mut w, contents.as_bytes()).unwrap();
+        }
+        ZipArchive::new(w.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn thread_table() {
+        let threads = read_threads(&mut archive(&[("threads", "1|main|main|false|10|900\n\n7|pool-1-thread-1|main|true|20|800\n")])).unwrap();
+        assert_eq!(threads.len(), 2);
+        let t = &threads[&7];
+        assert_eq!((t.id, t.name.as_str(), t.group.as_str(), t.daemon, t.first_seen, t.last_seen),
+                   (7, "pool-1-thread-1", "main", true, 20, 800));
+        assert!(!threads[&1].daemon);
+    }
+
+    #[test]
+    fn thread_table_rows_need_every_field() {
+        let e = read_threads(&mut archive(&[("threads", "1|main|main|false|10\n")])).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(read_threads(&mut archive(&[("threads", "x|main|main|false|10|20\n")])).is_err());
+    }
+
+    #[test]
+    fn old_archives_have_no_thread_table() {
+        let mut d = profile(&[(4, 0, 10, 1, 1)]);
+        d.threads = read_threads(&mut archive(&[("header", "4;\n10\n")])).unwrap();
+        assert!(d.threads.is_empty());
+        assert_eq!(d.thread_name(4), "Thread #4");
+    }
+
+    #[test]
+    fn call_tree_from_exit_order() {
+        let d = sample();
+        assert_eq!(d.parents, vec![Some(1), Some(3), Some(3), None, None]);
+        assert_eq!(d.children[3], vec![1, 2]);
+        assert_eq!(d.children[1], vec![0]);
+        assert_eq!(d.roots, vec![3, 4]);
+        assert_eq!(d.ancestors(0), vec![3, 1]);
+    }
+
+    #[test]
+    fn call_tree_keeps_threads_apart() {
+        // a deeper call on another thread that starts inside a root is not its child
+        let d = profile(&[(1, 0, 100, 1, 1), (2, 10, 10, 2, 2)]);
+        assert_eq!(d.parents, vec![None, None]);
+        assert_eq!(d.roots, vec![0, 1]);
+    }
+
+    #[test]
+    fn stats_and_invocations() {
+        let d = sample();
+        assert_eq!(d.self_times[3], 30);
+        assert_eq!(d.self_times[1], 25);
+        assert_eq!(d.invocations[&2], vec![1, 2]);
+        assert_eq!(d.invocation_index[2], 1);
+        assert_eq!(d.method_stats[&2].count, 2);
+        assert_eq!(d.method_stats[&2].max_time, 40);
+        assert_eq!(d.thread_busy_time[&1], 100);
+    }
+
+    #[test]
+    fn stacks_at_a_time() {
+        let d = sample();
+        assert_eq!(d.stacks_at(17), vec![(1, vec![3, 1, 0]), (2, vec![4])]);
+        assert_eq!(d.stacks_at(45), vec![(1, vec![3]), (2, vec![4])]);
+        assert_eq!(d.stacks_at(70), vec![(1, vec![3, 2])]);
+        // a call's end time is not inside it
+        assert_eq!(d.stacks_at(100), vec![]);
+    }
+}
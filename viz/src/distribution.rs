@@ -0,0 +1,335 @@
+use runic::*;
+use winit::*;
+
+use data::*;
+use menu::*;
+use view::*;
+use theme::*;
+
+/// Number of bars in the histogram
+const HISTOGRAM_BINS: usize = 64;
+
+/// Which plot a point falls in
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Plot {
+    Histogram,
+    Scatter
+}
+
+/// Histogram of how long every call of one method took on a log scale, above a scatter plot of when each call
+/// started against how long it took. Dragging out a box in either one filters the flame chart down to those calls
+pub struct DistributionView {
+    method_id: u32,
+    /// Start and elapsed time of every call
+    points: Vec<(u64, u64)>,
+    bins: Vec<usize>,
+    /// Scatter plot cells with at least one call in them, as runs of `(row, first column, last column)` so that
+    /// methods called millions of times draw as quickly as any other
+    scatter_runs: Vec<(usize, usize, usize)>,
+    /// Columns, rows and end time the scatter plot was binned for, so it is only rebuilt when they change
+    scatter_key: Option<(usize, usize, u64)>,
+    /// Natural log of the shortest and longest durations, the ends of both duration axes
+    log_range: (f64, f64),
+    last_mouse: Point,
+    /// Where a selection box was started
+    drag_start: Option<(Plot, Point)>,
+    histogram_bounds: Rect,
+    scatter_bounds: Rect,
+    /// Filter for the selected calls, handed back to the flame chart
+    selection: Option<String>,
+    bounds: Rect
+}
+
+impl DistributionView {
+    pub fn new(data: &VizData, method_id: u32) -> DistributionView {
+        let points: Vec<(u64, u64)> = data.invocations.get(&method_id).map(|calls| calls.iter()
+            .map(|&i| (data.calls[i].start_time, data.calls[i].elapsed_time)).collect()).unwrap_or_default();
+        let min = points.iter().map(|&(_, e)| e).min().unwrap_or(1).max(1);
+        let max = points.iter().map(|&(_, e)| e).max().unwrap_or(1).max(1);
+        let lmin = (min as f64).ln();
+        let lmax = if max > min { (max as f64).ln() } else { lmin + 1.0 };
+        let mut v = DistributionView {
+            method_id: method_id,
+            points: points,
+            bins: vec![0; HISTOGRAM_BINS],
+            scatter_runs: Vec::new(),
+            scatter_key: None,
+            log_range: (lmin, lmax),
+            last_mouse: Point::xy(0.0, 0.0),
+            drag_start: None,
+            histogram_bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            scatter_bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            selection: None,
+            bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0)
+        };
+        for i in 0..v.points.len() {
+            let b = ((v.duration_fraction(v.points[i].1) * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+            v.bins[b] += 1;
+        }
+        v
+    }
+
+    /// Where a duration falls along the log scaled axis, from 0 for the shortest to 1 for the longest
+    fn duration_fraction(&self, d: u64) -> f64 {
+        let (lmin, lmax) = self.log_range;
+        (((d.max(1) as f64).ln() - lmin) / (lmax - lmin)).max(0.0).min(1.0)
+    }
+
+    fn duration_at_fraction(&self, f: f64) -> u64 {
+        let (lmin, lmax) = self.log_range;
+        (lmin + f.max(0.0).min(1.0) * (lmax - lmin)).exp().round() as u64
+    }
+
+    /// Mark the cells of a `columns` by `rows` grid over the scatter plot that have a call in them
+    fn bin_scatter(&mut self, columns: usize, rows: usize, end_time: u64) {
+        let key = (columns, rows, end_time);
+        if self.scatter_key == Some(key) { return; }
+        self.scatter_key = Some(key);
+        let mut grid = vec![false; columns * rows];
+        for &(start, elapsed) in self.points.iter() {
+            let col = ((start as f64 / end_time.max(1) as f64 * columns as f64) as usize).min(columns - 1);
+            let row = (((1.0 - self.duration_fraction(elapsed)) * rows as f64) as usize).min(rows - 1);
+            grid[row * columns + col] = true;
+        }
+        self.scatter_runs.clear();
+        for row in 0..rows {
+            let mut col = 0;
+            while col < columns {
+                if !grid[row * columns + col] { col += 1; continue; }
+                let first = col;
+                while col + 1 < columns && grid[row * columns + col + 1] { col += 1; }
+                self.scatter_runs.push((row, first, col));
+                col += 1;
+            }
+        }
+    }
+
+    /// Range of durations covered by a histogram bar
+    fn bin_range(&self, b: usize) -> (u64, u64) {
+        (self.duration_at_fraction(b as f64 / HISTOGRAM_BINS as f64), self.duration_at_fraction((b + 1) as f64 / HISTOGRAM_BINS as f64))
+    }
+
+    fn plot_at(&self, p: Point) -> Option<Plot> {
+        if self.histogram_bounds.contains(p) { Some(Plot::Histogram) }
+        else if self.scatter_bounds.contains(p) { Some(Plot::Scatter) }
+        else { None }
+    }
+
+    /// Time along the scatter plot's horizontal axis
+    fn time_at(&self, x: f32, data: &VizData) -> u64 {
+        let r = self.scatter_bounds;
+        (((x - r.x) / r.w).max(0.0).min(1.0) as f64 * data.abs_end_time as f64) as u64
+    }
+
+    /// Duration along the scatter plot's vertical axis, longest at the top
+    fn duration_at_y(&self, y: f32) -> u64 {
+        let r = self.scatter_bounds;
+        self.duration_at_fraction(1.0 - ((y - r.y) / r.h) as f64)
+    }
+
+    /// Turn the box dragged out from `start` to `end` into a filter matching the calls inside it
+    fn select(&mut self, plot: Plot, start: Point, end: Point, data: &VizData) {
+        let (x0, x1) = (start.x.min(end.x), start.x.max(end.x));
+        let (y0, y1) = (start.y.min(end.y), start.y.max(end.y));
+        let mut src = format!("id == {}", data.method_key(self.method_id));
+        match plot {
+            Plot::Histogram => {
+                // a click without dragging picks the one bar under the mouse
+                let r = self.histogram_bounds;
+                let bar = |x: f32| ((((x - r.x) / r.w).max(0.0) * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1);
+                let (lo, _) = self.bin_range(bar(x0));
+                let (_, hi) = self.bin_range(bar(x1));
+                src.push_str(&format!(" and elapsed >= {} and elapsed <= {}", lo, hi));
+            },
+            Plot::Scatter => {
+                if x1 - x0 >= 4.0 {
+                    src.push_str(&format!(" and start >= {} and start <= {}", self.time_at(x0, data), self.time_at(x1, data)));
+                }
+                if y1 - y0 >= 4.0 {
+                    src.push_str(&format!(" and elapsed >= {} and elapsed <= {}", self.duration_at_y(y1), self.duration_at_y(y0)));
+                }
+            }
+        }
+        self.selection = Some(src);
+    }
+}
+
+impl VizView for DistributionView {
+    fn status(&self, data: &VizData) -> String {
+        format!("duration distribution of {} | {} calls | drag out a box to show those calls",
+                data.method_index.get(&self.method_id).cloned().unwrap_or(String::from("?")), self.points.len())
+    }
+
+    fn reset(&mut self) {
+        self.drag_start = None;
+    }
+
+    fn event(&mut self, e: &WindowEvent, data: &VizData, _menus: &mut MenuContext) -> bool {
+        match e {
+            &WindowEvent::CursorMoved { position: (x,y), .. } => {
+                self.last_mouse = Point::xy(x as f32, y as f32);
+            },
+            &WindowEvent::MouseInput{ state, button: MouseButton::Left, .. } => {
+                match state {
+                    ElementState::Pressed => {
+                        let m = self.last_mouse;
+                        self.drag_start = self.plot_at(m).map(|p| (p, m));
+                    },
+                    ElementState::Released => {
+                        if let Some((plot, start)) = self.drag_start.take() {
+                            let end = self.last_mouse;
+                            self.select(plot, start, end, data);
+                        }
+                    }
+                }
+                return true;
+            },
+            _ => {}
+        }
+        false
+    }
+
+    fn menu_selection(&mut self, _data: &VizData, _tag: &'static str, _sel: usize) {}
+
+    fn prompt_result(&mut self, _data: &VizData, _tag: &'static str, _text: &str, _menus: &mut MenuContext) {}
+
+    fn filter_source(&self) -> Option<&str> { None }
+
+    fn back_with_filter(&mut self) -> Option<String> {
+        self.selection.take()
+    }
+
+    fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData) {
+        self.bounds = rx.bounds();
+        let top = res.line_height + res.px(8.0);
+        let margin = res.px(8.0);
+        let label_h = res.line_height + res.px(4.0);
+        let plot_h = ((self.bounds.h - top - margin * 3.0 - label_h * 2.0) / 2.0).max(0.0);
+        self.histogram_bounds = Rect::xywh(margin, top + margin, self.bounds.w - margin * 2.0, plot_h);
+        self.scatter_bounds = Rect::xywh(margin, self.histogram_bounds.y + plot_h + label_h + margin, self.bounds.w - margin * 2.0, plot_h);
+        let (hb, sb) = (self.histogram_bounds, self.scatter_bounds);
+
+        for &r in [hb, sb].iter() {
+            rx.set_color(to_color(res.theme.panel_background));
+            rx.fill_rect(r);
+            rx.set_color(to_color(res.theme.panel_border));
+            rx.stroke_rect(r, res.px(1.0));
+        }
+
+        // histogram bars, with the shortest and longest durations and the tallest bar's count as labels
+        let max_count = self.bins.iter().cloned().max().unwrap_or(0).max(1);
+        let bar_w = hb.w / HISTOGRAM_BINS as f32;
+        let mut hovered_bar = None;
+        for (b, &count) in self.bins.iter().enumerate() {
+            let r = Rect::xywh(hb.x + b as f32 * bar_w, hb.y, bar_w, hb.h);
+            if r.contains(self.last_mouse) { hovered_bar = Some(b); }
+            if count == 0 { continue; }
+            let h = count as f32 / max_count as f32 * (hb.h - res.px(2.0));
+            rx.set_color(to_color(if hovered_bar == Some(b) { res.theme.panel_highlight } else { res.theme.text }));
+            rx.fill_rect(Rect::xywh(r.x + res.px(1.0), hb.y + hb.h - h, (bar_w - res.px(2.0)).max(1.0), h));
+        }
+        rx.set_color(to_color(res.theme.text));
+        rx.draw_text(Rect::xywh(hb.x + res.px(4.0), hb.y + res.px(2.0), hb.w, res.line_height), &format!("{} calls", max_count), &res.font);
+        let (dmin, dmax) = (self.duration_at_fraction(0.0), self.duration_at_fraction(1.0));
+        rx.draw_text(Rect::xywh(hb.x, hb.y + hb.h + res.px(2.0), hb.w / 2.0, res.line_height), &format_duration(dmin), &res.font);
+        let max_label = format_duration(dmax);
+        rx.draw_text(Rect::xywh(hb.x + hb.w - res.char_width * max_label.len() as f32, hb.y + hb.h + res.px(2.0), hb.w / 2.0, res.line_height),
+                     &max_label, &res.font);
+
+        // scatter plot, time along the bottom and the same log scaled durations going up
+        let dot = res.px(3.0);
+        let (columns, rows) = (((sb.w / dot) as usize).max(1), ((sb.h / dot) as usize).max(1));
+        self.bin_scatter(columns, rows, data.abs_end_time);
+        let (cell_w, cell_h) = (sb.w / columns as f32, sb.h / rows as f32);
+        rx.set_color(to_color(res.theme.text));
+        for &(row, first, last) in self.scatter_runs.iter() {
+            rx.fill_rect(Rect::xywh(sb.x + first as f32 * cell_w, sb.y + row as f32 * cell_h, (last - first + 1) as f32 * cell_w, cell_h));
+        }
+        rx.draw_text(Rect::xywh(sb.x + res.px(4.0), sb.y + res.px(2.0), sb.w, res.line_height), &max_label, &res.font);
+        rx.draw_text(Rect::xywh(sb.x + res.px(4.0), sb.y + sb.h - res.line_height - res.px(2.0), sb.w, res.line_height), &format_duration(dmin), &res.font);
+        rx.draw_text(Rect::xywh(sb.x, sb.y + sb.h + res.px(2.0), sb.w / 2.0, res.line_height), "0", &res.font);
+        let end_label = format_duration(data.abs_end_time);
+        rx.draw_text(Rect::xywh(sb.x + sb.w - res.char_width * end_label.len() as f32, sb.y + sb.h + res.px(2.0), sb.w / 2.0, res.line_height),
+                     &end_label, &res.font);
+
+        // selection box being dragged out, kept inside the plot it started in
+        if let Some((plot, start)) = self.drag_start {
+            let r = if plot == Plot::Histogram { hb } else { sb };
+            let (ex, ey) = (self.last_mouse.x.max(r.x).min(r.x + r.w), self.last_mouse.y.max(r.y).min(r.y + r.h));
+            let sel = if plot == Plot::Histogram {
+                Rect::xywh(start.x.min(ex), r.y, (start.x - ex).abs(), r.h)
+            } else {
+                Rect::xywh(start.x.min(ex), start.y.min(ey), (start.x - ex).abs(), (start.y - ey).abs())
+            };
+            rx.set_color(to_color(res.theme.panel_border));
+            rx.stroke_rect(sel, res.px(2.0));
+            return;
+        }
+
+        match self.plot_at(self.last_mouse) {
+            Some(Plot::Histogram) => if let Some(b) = hovered_bar {
+                let (lo, hi) = self.bin_range(b);
+                paint_tooltip(rx, res, &format!("{} to {}\n{} calls", format_duration(lo), format_duration(hi), self.bins[b]),
+                              self.last_mouse, self.bounds);
+            },
+            Some(Plot::Scatter) => {
+                paint_tooltip(rx, res, &format!("t = {}\nduration {}", format_duration(self.time_at(self.last_mouse.x, data)),
+                                                format_duration(self.duration_at_y(self.last_mouse.y))),
+                              self.last_mouse, self.bounds);
+            },
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+    use filter::Filter;
+
+    /// Method 2 takes 10, 100 and 1000ns, starting at 0, 400 and 800
+    fn sample() -> VizData {
+        let mut d = profile(&[(1, 0, 10, 2, 2), (1, 400, 100, 2, 2), (1, 800, 1000, 2, 2), (1, 0, 2000, 1, 1)]);
+        d.method_index.insert(2, String::from("com.acme.Foo.bar()"));
+        d
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let v = DistributionView::new(&sample(), 2);
+        // 100ns is halfway between 10ns and 1000ns on a log scale
+        let filled: Vec<(usize, usize)> = v.bins.iter().cloned().enumerate().filter(|&(_, n)| n > 0).collect();
+        assert_eq!(filled, vec![(0, 1), (HISTOGRAM_BINS / 2, 1), (HISTOGRAM_BINS - 1, 1)]);
+        assert_eq!(v.bins.iter().sum::<usize>(), 3);
+        assert_eq!(v.bin_range(0).0, 10);
+        assert_eq!(v.bin_range(HISTOGRAM_BINS - 1).1, 1000);
+        assert_eq!(v.bin_range(HISTOGRAM_BINS / 2).0, 100);
+    }
+
+    #[test]
+    fn selection_filters() {
+        let d = sample();
+        let key = d.method_key(2);
+        let mut v = DistributionView::new(&d, 2);
+        v.histogram_bounds = Rect::xywh(0.0, 0.0, 640.0, 100.0);
+        v.scatter_bounds = Rect::xywh(0.0, 200.0, 100.0, 100.0);
+
+        // clicking the first bar picks the shortest call
+        v.select(Plot::Histogram, Point::xy(1.0, 50.0), Point::xy(1.0, 50.0), &d);
+        let src = v.back_with_filter().unwrap();
+        assert_eq!(src, format!("id == {} and elapsed >= 10 and elapsed <= 11", key));
+        let f = Filter::parse(&src).unwrap();
+        let picked: Vec<usize> = (0..d.calls.len()).filter(|&i| f.matches(&d.calls[i], &d)).collect();
+        assert_eq!(picked, vec![0]);
+
+        // a box over the first 30% of the scatter plot and every duration
+        v.select(Plot::Scatter, Point::xy(0.0, 200.0), Point::xy(30.0, 300.0), &d);
+        let src = v.back_with_filter().unwrap();
+        assert_eq!(src, format!("id == {} and start >= 0 and start <= 600 and elapsed >= 10 and elapsed <= 1000", key));
+        let f = Filter::parse(&src).unwrap();
+        let picked: Vec<usize> = (0..d.calls.len()).filter(|&i| f.matches(&d.calls[i], &d)).collect();
+        assert_eq!(picked, vec![0, 1]);
+        assert!(v.back_with_filter().is_none());
+    }
+}
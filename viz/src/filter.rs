@@ -0,0 +1,434 @@
+use std::fmt;
+use std::error::Error;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::Regex;
+
+use data::*;
+
+/// Fields of a call record that can be referenced in a filter expression
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Field {
+    Method,
+    /// The method's stable key, the same for a method in every capture unlike the agent's ids
+    MethodKey,
+    Thread,
+    Start,
+    Elapsed,
+    End,
+    Depth
+}
+
+impl Field {
+    fn from_name(s: &str) -> Option<Field> {
+        match s {
+            "method" | "name" => Some(Field::Method),
+            "id" | "key" | "method_key" => Some(Field::MethodKey),
+            "thread" => Some(Field::Thread),
+            "start" => Some(Field::Start),
+            "elapsed" | "duration" => Some(Field::Elapsed),
+            "end" => Some(Field::End),
+            "depth" => Some(Field::Depth),
+            _ => None
+        }
+    }
+
+    fn number(&self, cr: &CallRecord, data: &VizData) -> u64 {
+        match *self {
+            Field::MethodKey => data.method_key(cr.method_id),
+            Field::Thread => cr.thread_id as u64,
+            Field::Start => cr.start_time,
+            Field::Elapsed => cr.elapsed_time,
+            Field::End => cr.start_time + cr.elapsed_time,
+            Field::Depth => cr.depth as u64,
+            Field::Method => unreachable!()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CmpOp { Eq, Ne, Lt, Le, Gt, Ge }
+
+impl CmpOp {
+    fn test<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match *self {
+            CmpOp::Eq => a == b,
+            CmpOp::Ne => a != b,
+            CmpOp::Lt => a < b,
+            CmpOp::Le => a <= b,
+            CmpOp::Gt => a > b,
+            CmpOp::Ge => a >= b
+        }
+    }
+}
+
+/// A predicate over call records, parsed from an expression like
+/// `method ~ "Dao" and elapsed > 5ms and thread in (12,14) and depth < 10`
+#[derive(Debug)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(Field, CmpOp, u64),
+    In(Field, Vec<u64>),
+    MethodCompare(CmpOp, String),
+    MethodIn(Vec<String>),
+    MethodMatches(Regex)
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub pos: usize,
+    pub msg: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "filter error at {}: {}", self.pos, self.msg)
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str { &self.msg }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(u64),
+    Str(String),
+    Op(CmpOp),
+    Tilde,
+    LParen, RParen, Comma,
+    End
+}
+
+struct Lexer<'s> {
+    chars: Peekable<Chars<'s>>,
+    pos: usize
+}
+
+impl<'s> Lexer<'s> {
+    fn bump(&mut self) -> Option<char> {
+        self.pos += 1;
+        self.chars.next()
+    }
+
+    /// Error for the token starting at `pos`
+    fn err<T>(&self, pos: usize, msg: &str) -> Result<T, ParseError> {
+        Err(ParseError { pos: pos, msg: String::from(msg) })
+    }
+
+    fn next_token(&mut self) -> Result<(usize, Token), ParseError> {
+        while self.chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) { self.bump(); }
+        let start = self.pos;
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Ok((start, Token::End))
+        };
+        let t = match c {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '~' => Token::Tilde,
+            '=' => { if self.chars.peek() == Some(&'=') { self.bump(); } Token::Op(CmpOp::Eq) },
+            '!' => {
+                if self.bump() != Some('=') { return self.err(start, "expected '=' after '!'"); }
+                Token::Op(CmpOp::Ne)
+            },
+            '<' | '>' => {
+                let eq = self.chars.peek() == Some(&'=');
+                if eq { self.bump(); }
+                Token::Op(match (c, eq) {
+                    ('<', false) => CmpOp::Lt, ('<', true) => CmpOp::Le,
+                    ('>', false) => CmpOp::Gt, _ => CmpOp::Ge
+                })
+            },
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        Some(q) if q == c => break,
+                        Some('\\') => match self.bump() {
+                            Some(e) => s.push(e),
+                            None => return self.err(start, "unterminated string")
+                        },
+                        Some(x) => s.push(x),
+                        None => return self.err(start, "unterminated string")
+                    }
+                }
+                Token::Str(s)
+            },
+            _ if c.is_digit(10) => {
+                let mut digits = c.to_string();
+                while self.chars.peek().map(|c| c.is_digit(10)).unwrap_or(false) { digits.push(self.bump().unwrap()); }
+                let mut unit = String::new();
+                while self.chars.peek().map(|c| c.is_alphabetic()).unwrap_or(false) { unit.push(self.bump().unwrap()); }
+                let v = digits.parse::<u64>().map_err(|e| ParseError { pos: start, msg: e.to_string() })?;
+                let scale = match unit.as_str() {
+                    "" | "ns" => 1,
+                    "us" => 1_000,
+                    "ms" => 1_000_000,
+                    "s" => 1_000_000_000,
+                    _ => return Err(ParseError { pos: start, msg: format!("unknown unit '{}'", unit) })
+                };
+                Token::Number(v.checked_mul(scale).ok_or(ParseError { pos: start, msg: format!("'{}{}' is too large", digits, unit) })?)
+            },
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut s = c.to_string();
+                while self.chars.peek().map(|c| c.is_alphanumeric() || *c == '_').unwrap_or(false) { s.push(self.bump().unwrap()); }
+                Token::Ident(s.to_lowercase())
+            },
+            _ => return Err(ParseError { pos: start, msg: format!("unexpected character '{}'", c) })
+        };
+        Ok((start, t))
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize
+}
+
+impl Parser {
+    fn peek(&self) -> &Token { &self.tokens[self.next].1 }
+    fn pos(&self) -> usize { self.tokens[self.next].0 }
+
+    fn bump(&mut self) -> Token {
+        let t = self.tokens[self.next].1.clone();
+        if t != Token::End { self.next += 1; }
+        t
+    }
+
+    fn err<T>(&self, msg: &str) -> Result<T, ParseError> {
+        Err(ParseError { pos: self.pos(), msg: String::from(msg) })
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        let is_kw = match self.peek() { &Token::Ident(ref s) => s == kw, _ => false };
+        if is_kw { self.bump(); }
+        is_kw
+    }
+
+    fn expect(&mut self, t: Token, msg: &str) -> Result<(), ParseError> {
+        if *self.peek() == t { self.bump(); Ok(()) } else { self.err(msg) }
+    }
+
+    fn or_expr(&mut self) -> Result<Filter, ParseError> {
+        let mut f = self.and_expr()?;
+        while self.keyword("or") {
+            f = Filter::Or(Box::new(f), Box::new(self.and_expr()?));
+        }
+        Ok(f)
+    }
+
+    fn and_expr(&mut self) -> Result<Filter, ParseError> {
+        let mut f = self.unary()?;
+        while self.keyword("and") {
+            f = Filter::And(Box::new(f), Box::new(self.unary()?));
+        }
+        Ok(f)
+    }
+
+    fn unary(&mut self) -> Result<Filter, ParseError> {
+        if self.keyword("not") {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if *self.peek() == Token::LParen {
+            self.bump();
+            let f = self.or_expr()?;
+            self.expect(Token::RParen, "expected ')'")?;
+            return Ok(f);
+        }
+        self.comparison()
+    }
+
+    /// Values of an `in` list with where each one starts
+    fn value_list(&mut self) -> Result<Vec<(usize, Token)>, ParseError> {
+        self.expect(Token::LParen, "expected '(' after 'in'")?;
+        let mut vs = Vec::new();
+        loop {
+            vs.push((self.pos(), self.bump()));
+            match *self.peek() {
+                Token::Comma => { self.bump(); },
+                Token::RParen => { self.bump(); break; },
+                _ => return self.err("expected ',' or ')' in value list")
+            }
+        }
+        Ok(vs)
+    }
+
+    fn comparison(&mut self) -> Result<Filter, ParseError> {
+        let field_pos = self.pos();
+        let field = match self.bump() {
+            Token::Ident(ref s) => Field::from_name(s).ok_or(ParseError { pos: field_pos, msg: format!("unknown field '{}'", s) })?,
+            _ => return Err(ParseError { pos: field_pos, msg: String::from("expected field name") })
+        };
+        let type_err = |pos: usize, what: &str| ParseError { pos: pos, msg: format!("expected {} value for {:?}", what, field) };
+        if self.keyword("in") {
+            let vs = self.value_list()?;
+            if field == Field::Method {
+                let mut ss = Vec::new();
+                for (pos, v) in vs { match v { Token::Str(s) => ss.push(s), _ => return Err(type_err(pos, "string")) } }
+                Ok(Filter::MethodIn(ss))
+            } else {
+                let mut ns = Vec::new();
+                for (pos, v) in vs { match v { Token::Number(n) => ns.push(n), _ => return Err(type_err(pos, "numeric")) } }
+                Ok(Filter::In(field, ns))
+            }
+        } else {
+            let op_pos = self.pos();
+            let op = match self.bump() {
+                Token::Op(op) => Some(op),
+                Token::Tilde => None,
+                _ => return Err(ParseError { pos: op_pos, msg: String::from("expected comparison operator") })
+            };
+            let value_pos = self.pos();
+            match (field, op, self.bump()) {
+                (Field::Method, None, Token::Str(s)) =>
+                    Regex::new(&s).map(Filter::MethodMatches)
+                        .map_err(|e| ParseError { pos: value_pos, msg: e.to_string() }),
+                (Field::Method, Some(op), Token::Str(s)) => Ok(Filter::MethodCompare(op, s)),
+                (Field::Method, _, _) => Err(type_err(value_pos, "string")),
+                (_, None, _) => Err(ParseError { pos: op_pos, msg: String::from("'~' only applies to method") }),
+                (f, Some(op), Token::Number(n)) => Ok(Filter::Compare(f, op, n)),
+                (_, _, _) => Err(type_err(value_pos, "numeric"))
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(src: &str) -> Result<Filter, ParseError> {
+        let mut lx = Lexer { chars: src.chars().peekable(), pos: 0 };
+        let mut tokens = Vec::new();
+        loop {
+            let t = lx.next_token()?;
+            let end = t.1 == Token::End;
+            tokens.push(t);
+            if end { break; }
+        }
+        let mut p = Parser { tokens: tokens, next: 0 };
+        let f = p.or_expr()?;
+        if *p.peek() != Token::End {
+            return p.err("unexpected input after expression");
+        }
+        Ok(f)
+    }
+
+    pub fn matches(&self, cr: &CallRecord, data: &VizData) -> bool {
+        match *self {
+            Filter::And(ref a, ref b) => a.matches(cr, data) && b.matches(cr, data),
+            Filter::Or(ref a, ref b) => a.matches(cr, data) || b.matches(cr, data),
+            Filter::Not(ref a) => !a.matches(cr, data),
+            Filter::Compare(f, op, v) => op.test(f.number(cr, data), v),
+            Filter::In(f, ref vs) => vs.contains(&f.number(cr, data)),
+            Filter::MethodCompare(op, ref s) =>
+                op.test(data.method_index.get(&cr.method_id).map(|m| m.as_str()).unwrap_or(""), s.as_str()),
+            Filter::MethodIn(ref ss) =>
+                data.method_index.get(&cr.method_id).map(|m| ss.contains(m)).unwrap_or(false),
+            Filter::MethodMatches(ref re) =>
+                data.method_index.get(&cr.method_id).map(|m| re.is_match(m)).unwrap_or(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(method_id: u32, elapsed: u64, thread_id: u32) -> CallRecord {
+        CallRecord { thread_id: thread_id, start_time: 0, elapsed_time: elapsed, method_id: method_id, depth: 1 }
+    }
+
+    fn data() -> VizData {
+        let mut d = VizData::default();
+        d.method_index.insert(1, String::from("com.acme.FooDao.find()"));
+        d.method_index.insert(2, String::from("com.acme.Bar.run()"));
+        d
+    }
+
+    fn err_pos(src: &str) -> usize {
+        match Filter::parse(src) {
+            Err(e) => e.pos,
+            Ok(f) => panic!("'{}' parsed as {:?}", src, f)
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        match Filter::parse("depth < 2 or depth > 5 and thread == 3").unwrap() {
+            Filter::Or(a, b) => {
+                match *a { Filter::Compare(Field::Depth, CmpOp::Lt, 2) => {}, ref f => panic!("left side {:?}", f) }
+                match *b { Filter::And(_, _) => {}, ref f => panic!("right side {:?}", f) }
+            },
+            f => panic!("parsed as {:?}", f)
+        }
+    }
+
+    #[test]
+    fn parentheses_and_not() {
+        let d = data();
+        let f = Filter::parse("not (thread == 1 or thread == 2) and elapsed >= 10").unwrap();
+        assert!(f.matches(&call(1, 10, 3), &d));
+        assert!(!f.matches(&call(1, 10, 2), &d));
+        assert!(!f.matches(&call(1, 9, 3), &d));
+    }
+
+    #[test]
+    fn in_lists() {
+        let d = data();
+        let f = Filter::parse("thread in (3, 5,7)").unwrap();
+        assert!(f.matches(&call(1, 0, 5), &d));
+        assert!(!f.matches(&call(1, 0, 4), &d));
+        let f = Filter::parse("method in (\"com.acme.Bar.run()\")").unwrap();
+        assert!(f.matches(&call(2, 0, 1), &d));
+        assert!(!f.matches(&call(1, 0, 1), &d));
+        assert_eq!(err_pos("thread in (1, \"x\")"), 14);
+        assert_eq!(err_pos("thread in (1 2)"), 13);
+    }
+
+    #[test]
+    fn method_regex() {
+        let d = data();
+        let f = Filter::parse("method ~ 'Dao\\.'").unwrap();
+        assert!(f.matches(&call(1, 0, 1), &d));
+        assert!(!f.matches(&call(2, 0, 1), &d));
+        assert_eq!(err_pos("depth ~ 'x'"), 6);
+        assert_eq!(err_pos("method ~ '('"), 9);
+    }
+
+    #[test]
+    fn units() {
+        for &(src, v) in [("5", 5), ("5ns", 5), ("5us", 5_000), ("5ms", 5_000_000), ("5s", 5_000_000_000)].iter() {
+            match Filter::parse(&format!("elapsed > {}", src)).unwrap() {
+                Filter::Compare(Field::Elapsed, CmpOp::Gt, n) => assert_eq!(n, v),
+                f => panic!("'{}' parsed as {:?}", src, f)
+            }
+        }
+        assert_eq!(err_pos("elapsed > 5min"), 10);
+        assert_eq!(err_pos("elapsed > 99999999999s"), 10);
+        assert_eq!(err_pos("elapsed > 99999999999999999999"), 10);
+    }
+
+    #[test]
+    fn method_key_is_stable() {
+        let d = data();
+        let f = Filter::parse(&format!("id == {}", d.method_key(1))).unwrap();
+        assert!(f.matches(&call(1, 0, 1), &d));
+        assert!(!f.matches(&call(2, 0, 1), &d));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(err_pos("elapsed > 5 and foo < 3"), 16);
+        assert_eq!(err_pos("elapsed 5"), 8);
+        assert_eq!(err_pos("(depth < 2"), 10);
+        assert_eq!(err_pos("depth < 2 depth"), 10);
+        assert_eq!(err_pos("method == 3"), 10);
+        assert_eq!(err_pos("depth < 'a'"), 8);
+        assert_eq!(err_pos("depth ! 2"), 6);
+        assert_eq!(err_pos("method == 'abc"), 10);
+    }
+}
@@ -0,0 +1,50 @@
+use runic::*;
+
+use data::*;
+use view::Resources;
+use theme::*;
+use names::fit_str;
+use threads::thread_label;
+
+/// Width of the stack panel at a hidpi factor of 1
+const PANEL_WIDTH: f32 = 480.0;
+
+/// Draw a vertical line at `x` through `area` and a panel listing what every thread was doing at `time`.
+/// The panel goes on the side of the window away from the line so it doesn't cover what is being inspected
+pub fn paint_instant(rx: &mut RenderContext, res: &Resources, data: &VizData, time: u64, x: f32, area: Rect) {
+    rx.set_color(to_color(res.theme.text));
+    rx.draw_line(Point::xy(x, area.y), Point::xy(x, area.y + area.h), res.px(1.0));
+
+    let stacks = data.stacks_at(time);
+    let line_h = res.line_height;
+    let mut lines: Vec<(String, bool)> = vec![(format!("t = {}", format_duration(time)), true)];
+    for &(thread, ref stack) in stacks.iter() {
+        lines.push((thread_label(data, thread), true));
+        for (depth, &c) in stack.iter().enumerate() {
+            let cr = &data.calls[c];
+            let name = data.method_names.get(&cr.method_id).map(|m| m.short_name())
+                .or_else(|| data.method_index.get(&cr.method_id).cloned()).unwrap_or(String::from("?"));
+            lines.push((format!("{}{} ({})", "  ".repeat(depth + 1), name, format_duration(cr.elapsed_time)), false));
+        }
+    }
+    let idle = data.thread_ids.len().saturating_sub(stacks.len());
+    if idle > 0 {
+        lines.push((format!("{} threads idle", idle), true));
+    }
+
+    let w = res.px(PANEL_WIDTH).min(area.w / 2.0);
+    let max_lines = ((area.h - res.px(8.0)) / line_h).max(1.0) as usize;
+    let shown = lines.len().min(max_lines);
+    let bnd = Rect::xywh(if x > area.x + area.w / 2.0 { area.x + res.px(4.0) } else { area.x + area.w - w - res.px(4.0) },
+                         area.y + res.px(4.0), w, shown as f32 * line_h + res.px(8.0));
+    rx.set_color(to_color(res.theme.tooltip_background));
+    rx.fill_rect(bnd);
+    rx.set_color(to_color(res.theme.tooltip_border));
+    rx.stroke_rect(bnd, res.px(1.0));
+    let max_chars = ((w - res.px(8.0)) / res.char_width).max(0.0) as usize;
+    for (i, &(ref text, heading)) in lines.iter().take(shown).enumerate() {
+        let text = if i + 1 == shown && shown < lines.len() { format!("... {} more lines", lines.len() - shown + 1) } else { fit_str(text, max_chars) };
+        rx.set_color(to_color(if heading { res.theme.tooltip_text } else { res.theme.panel_text }));
+        rx.draw_text(Rect::xywh(bnd.x + res.px(4.0), bnd.y + res.px(4.0) + i as f32 * line_h, w - res.px(8.0), line_h), &text, &res.font);
+    }
+}
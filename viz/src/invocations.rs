@@ -0,0 +1,151 @@
+use data::*;
+
+/// Which other calls of a method count when stepping through its invocations
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InvocationScope {
+    AllThreads,
+    SameThread,
+    /// Called from the same method as the call being stepped from
+    SameCaller
+}
+
+impl InvocationScope {
+    pub fn next(self) -> InvocationScope {
+        match self {
+            InvocationScope::AllThreads => InvocationScope::SameThread,
+            InvocationScope::SameThread => InvocationScope::SameCaller,
+            InvocationScope::SameCaller => InvocationScope::AllThreads
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            InvocationScope::AllThreads => "all threads",
+            InvocationScope::SameThread => "same thread",
+            InvocationScope::SameCaller => "same caller"
+        }
+    }
+}
+
+fn caller(data: &VizData, call: usize) -> Option<u32> {
+    data.parents.get(call).cloned().unwrap_or(None).map(|p| data.calls[p].method_id)
+}
+
+/// Every call of the same method as `call` within `scope`, in start order
+pub fn invocations_of(data: &VizData, call: usize, scope: InvocationScope) -> Vec<usize> {
+    let cr = &data.calls[call];
+    let all = match data.invocations.get(&cr.method_id) {
+        Some(all) => all,
+        None => return vec![call]
+    };
+    match scope {
+        InvocationScope::AllThreads => all.clone(),
+        InvocationScope::SameThread => all.iter().cloned().filter(|&i| data.calls[i].thread_id == cr.thread_id).collect(),
+        InvocationScope::SameCaller => {
+            let c = caller(data, call);
+            all.iter().cloned().filter(|&i| caller(data, i) == c).collect()
+        }
+    }
+}
+
+/// Invocations of the selected call's method within a scope, kept until the call, scope or data changes so
+/// they aren't gathered again every frame
+pub struct InvocationCursor {
+    /// Call, scope, number of calls and whether loading had finished when the list was built
+    key: Option<(usize, InvocationScope, usize, bool)>,
+    calls: Vec<usize>,
+    position: usize
+}
+
+impl InvocationCursor {
+    pub fn new() -> InvocationCursor {
+        InvocationCursor { key: None, calls: Vec::new(), position: 0 }
+    }
+
+    fn key(data: &VizData, call: usize, scope: InvocationScope) -> (usize, InvocationScope, usize, bool) {
+        (call, scope, data.calls.len(), data.loaded)
+    }
+
+    pub fn update(&mut self, data: &VizData, call: usize, scope: InvocationScope) {
+        let key = InvocationCursor::key(data, call, scope);
+        if self.key == Some(key) { return; }
+        self.key = Some(key);
+        self.calls = invocations_of(data, call, scope);
+        self.position = self.calls.iter().position(|&i| i == call).unwrap_or(0);
+    }
+
+    /// Position of `call` among its invocations within `scope` and how many there are, if the cursor is up to date
+    pub fn position(&self, data: &VizData, call: usize, scope: InvocationScope) -> Option<(usize, usize)> {
+        if self.key == Some(InvocationCursor::key(data, call, scope)) {
+            Some((self.position, self.calls.len()))
+        } else {
+            None
+        }
+    }
+
+    /// The invocation after or before `call` within `scope`, if there is one
+    pub fn step(&mut self, data: &VizData, call: usize, scope: InvocationScope, forward: bool) -> Option<usize> {
+        self.update(data, call, scope);
+        if forward {
+            self.calls.get(self.position + 1).cloned()
+        } else if self.position > 0 {
+            Some(self.calls[self.position - 1])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    /// Method 2 is called twice by method 1 on thread 1, once by method 1 on thread 2 and once by method 3 on thread 1
+    fn sample() -> VizData {
+        profile(&[
+            (1, 10, 10, 2, 2),
+            (1, 30, 10, 2, 2),
+            (1, 0, 100, 1, 1),
+            (2, 60, 10, 2, 2),
+            (2, 50, 100, 1, 1),
+            (1, 210, 10, 2, 2),
+            (1, 200, 100, 3, 1)
+        ])
+    }
+
+    #[test]
+    fn invocations_in_scope() {
+        let d = sample();
+        assert_eq!(invocations_of(&d, 1, InvocationScope::AllThreads), vec![0, 1, 3, 5]);
+        assert_eq!(invocations_of(&d, 1, InvocationScope::SameThread), vec![0, 1, 5]);
+        assert_eq!(invocations_of(&d, 1, InvocationScope::SameCaller), vec![0, 1, 3]);
+        assert_eq!(invocations_of(&d, 5, InvocationScope::SameCaller), vec![5]);
+    }
+
+    #[test]
+    fn step_through_invocations() {
+        let d = sample();
+        let mut cursor = InvocationCursor::new();
+        assert_eq!(cursor.step(&d, 1, InvocationScope::AllThreads, true), Some(3));
+        assert_eq!(cursor.step(&d, 1, InvocationScope::AllThreads, false), Some(0));
+        assert_eq!(cursor.step(&d, 1, InvocationScope::SameThread, true), Some(5));
+        assert_eq!(cursor.step(&d, 5, InvocationScope::SameThread, true), None);
+        assert_eq!(cursor.step(&d, 0, InvocationScope::SameThread, false), None);
+        assert_eq!(cursor.step(&d, 5, InvocationScope::SameCaller, true), None);
+    }
+
+    #[test]
+    fn cursor_position_follows_selection_and_scope() {
+        let d = sample();
+        let mut cursor = InvocationCursor::new();
+        assert_eq!(cursor.position(&d, 3, InvocationScope::AllThreads), None);
+        cursor.update(&d, 3, InvocationScope::AllThreads);
+        assert_eq!(cursor.position(&d, 3, InvocationScope::AllThreads), Some((2, 4)));
+        // out of date once the scope or call changes, until it is updated again
+        assert_eq!(cursor.position(&d, 3, InvocationScope::SameThread), None);
+        assert_eq!(cursor.position(&d, 1, InvocationScope::AllThreads), None);
+        cursor.update(&d, 3, InvocationScope::SameThread);
+        assert_eq!(cursor.position(&d, 3, InvocationScope::SameThread), Some((0, 1)));
+    }
+}
@@ -4,12 +4,19 @@ extern crate winit;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate zip;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
 use std::io;
 use std::io::{BufRead, BufReader};
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::iter::{FromIterator, repeat};
+use std::error::Error;
+use std::path::Path;
 
 use runic::*;
 use winit::*;
@@ -19,6 +26,7 @@ use std::thread::Thread;
 use std::sync::{Arc, RwLock, TryLockError};
 use std::sync::atomic::{AtomicBool, Ordering};
 
+mod names;
 mod data;
 use data::{VizData};
 mod view;
@@ -27,43 +35,131 @@ use view::*;
 mod menu;
 use menu::*;
 
+mod filter;
+use filter::Filter;
+
+mod aggregate;
+
+mod transform;
+use transform::*;
+
+mod colors;
+use colors::ColorScheme;
+
+mod theme;
+use theme::*;
+
+mod minimap;
+mod threads;
+mod pools;
+mod inspector;
+mod stackpane;
+mod invocations;
+mod slowest;
+mod distribution;
+
+mod concurrency;
+use concurrency::ConcurrencyView;
+
 struct VizApp {
     data: Arc<RwLock<VizData>>,
+    /// Copy of the data with frame rules applied, if there are any
+    view_data: Option<VizData>,
+    rules: RuleSet,
+    rules_dirty: bool,
+    /// The rules file had lines that couldn't be read, saving would lose them
+    rules_read_only: bool,
     res: Resources,
     view: Box<VizView>,
+    /// Views to return to, most recent last
+    prev_views: Vec<Box<VizView>>,
     mx: MenuContext,
-    last_mouse: Point
+    last_mouse: Point,
+    themes: Vec<Theme>,
+    /// Theme picked from the menu, applied on the next paint since changing fonts needs the render context
+    pending_theme: Option<usize>,
+    /// New hidpi factor after the window moved to another screen, applied on the next paint like themes
+    pending_scale: Option<f32>,
+    /// Number of the data's load errors that have been shown already
+    errors_shown: usize
 }
 
 impl VizApp {
-    fn init(rx: &mut RenderContext) -> VizApp {
+    fn init(rx: &mut RenderContext, scale: f32) -> VizApp {
         let mut args = std::env::args().skip(1);
         let data = Arc::new(RwLock::new(args.next().map(|perf_path| VizData::new(perf_path)).unwrap_or_default()));
         if data.read().unwrap().path.is_some() {
-            let tdata = data.clone();
-            let t = thread::spawn(move || { VizData::load(tdata).expect("load viz data"); });
+            VizData::load_in_background(data.clone());
+        }
+        let (rules, mut errors) = match data.read().unwrap().path.as_ref() {
+            Some(p) => load_rules(p),
+            None => (RuleSet::new(), Vec::new())
+        };
+        let rules_read_only = errors.len() > 0;
+        let (themes, mut theme_errors) = Theme::available();
+        errors.append(&mut theme_errors);
+        let res = Resources::init(rx, themes[0].clone(), scale).expect("create graphics resources");
+        let mut mx = MenuContext::new();
+        if errors.len() > 0 {
+            mx.popup(errors.iter().map(|e| e.as_str()).collect(), Point::default(), "err");
         }
-        let res = Resources::init(rx).expect("create graphics resources");
         VizApp {
             data: data,
+            view_data: None,
+            rules: rules,
+            rules_dirty: true,
+            rules_read_only: rules_read_only,
             res: res,
             view: Box::new(FlameChart::init(rx)),
-            mx: MenuContext::new(),
+            prev_views: Vec::new(),
+            mx: mx,
             last_mouse: Point::default(),
+            themes: themes,
+            pending_theme: None,
+            pending_scale: None,
+            errors_shown: 0,
         }
     }
 }
 
 impl App for VizApp {
     fn paint(&mut self, rx: &mut RenderContext) {
-        rx.clear(Color::rgb(0.1, 0.1, 0.11));
+        if let Some(i) = self.pending_theme.take() {
+            let theme = self.themes[i].clone();
+            if let Err(e) = self.res.set_theme(rx, theme) {
+                self.mx.popup(vec![&format!("could not switch theme: {}", e)], self.last_mouse, "err");
+            }
+        }
+        if let Some(scale) = self.pending_scale.take() {
+            if let Err(e) = self.res.set_scale(rx, scale) {
+                self.mx.popup(vec![&format!("could not reload font: {}", e)], self.last_mouse, "err");
+            }
+        }
+        rx.clear(to_color(self.res.theme.background));
 
         match self.data.try_read() {
             Ok(d) => {
+                if self.rules_dirty && d.loaded {
+                    let had_rules = self.view_data.is_some();
+                    self.view_data = self.rules.apply(&d);
+                    self.rules_dirty = false;
+                    if had_rules || self.view_data.is_some() {
+                        if !self.prev_views.is_empty() {
+                            self.view = self.prev_views.remove(0);
+                            self.prev_views.clear();
+                        }
+                        self.view.reset();
+                    }
+                }
+                if d.errors.len() > self.errors_shown {
+                    self.mx.popup(d.errors[self.errors_shown..].iter().map(|e| e.as_str()).collect(), self.last_mouse, "err");
+                    self.errors_shown = d.errors.len();
+                }
+                let vd = self.view_data.as_ref().unwrap_or(&*d);
                 let bounds = rx.bounds();
                 let status_text = match d.path.as_ref() {
                     Some(p) => format!("{} | {} records {}[{}]",
-                                       self.view.status(&d),
+                                       self.view.status(vd),
                                        d.calls.len(),
                                        if !d.loaded { "[still loading...] " } else { "" },
                                        d.path.as_ref().unwrap().display()),
@@ -71,16 +167,20 @@ impl App for VizApp {
                 };
                 let status_tx = rx.new_text_layout(&status_text,
                                                    &self.res.font, bounds.w, bounds.h).expect("create status text layout");
-                rx.set_color(Color::rgb(0.3, 0.3, 0.3));
-                rx.fill_rect(Rect::xywh(0.0, 0.0, bounds.w, status_tx.bounds().h+2.0));
-                rx.set_color(Color::rgb(0.8, 0.8, 0.8));
-                rx.draw_text_layout(Point::xy(2.0, 0.0), &status_tx);
-                self.view.paint(rx, &self.res, &d);
+                rx.set_color(to_color(self.res.theme.status_background));
+                rx.fill_rect(Rect::xywh(0.0, 0.0, bounds.w, status_tx.bounds().h+self.res.px(2.0)));
+                rx.set_color(to_color(self.res.theme.status_text));
+                rx.draw_text_layout(Point::xy(self.res.px(2.0), 0.0), &status_tx);
+                self.view.paint(rx, &self.res, vd);
+                let view_errors = self.view.take_errors();
+                if view_errors.len() > 0 {
+                    self.mx.popup(view_errors.iter().map(|e| e.as_str()).collect(), self.last_mouse, "err");
+                }
                 self.mx.paint(rx, &self.res);
             },
             Err(TryLockError::WouldBlock) => {
-                rx.set_color(Color::rgb(0.7, 0.7, 0.7));
-                rx.draw_text(Rect::xywh(32.0, 32.0, 1000.0, 1000.0), "loading...", &self.res.font);
+                rx.set_color(to_color(self.res.theme.text));
+                rx.draw_text(Rect::xywh(self.res.px(32.0), self.res.px(32.0), self.res.px(1000.0), self.res.px(1000.0)), "loading...", &self.res.font);
             },
             Err(TryLockError::Poisoned(e)) => {
                 panic!("poisoned lock {}", e);
@@ -94,19 +194,38 @@ impl App for VizApp {
                 WindowEvent::CursorMoved { position, .. } => {
                     self.last_mouse = Point::from(position);
                 },
+                WindowEvent::HiDPIFactorChanged(scale) => {
+                    self.pending_scale = Some(scale);
+                },
                 WindowEvent::DroppedFile(ref path) => {
                     let mut dw = self.data.write().unwrap();
                     if !dw.loaded { 
                         self.mx.popup(vec!["please wait for current file to load before replacing it"], self.last_mouse, "err");
                     } else {
                         dw.path = Some(path.clone());
-                        let tdata = self.data.clone();
-                        let _ = thread::spawn(move || { VizData::load(tdata).expect("load viz data"); });
+                        let (rules, errors) = load_rules(path);
+                        if errors.len() > 0 {
+                            self.mx.popup(errors.iter().map(|e| e.as_str()).collect(), self.last_mouse, "err");
+                        }
+                        self.rules = rules;
+                        self.rules_read_only = errors.len() > 0;
+                        self.rules_dirty = true;
+                        self.view_data = None;
+                        if !self.prev_views.is_empty() {
+                            self.view = self.prev_views.remove(0);
+                            self.prev_views.clear();
+                        }
+                        // selections, threads and the filter belong to the old file
+                        self.view.reset_for_new_file();
+                        self.errors_shown = 0;
+                        VizData::load_in_background(self.data.clone());
                     }
                 },
                 _ => {}
             }
             let d = self.data.read().unwrap();
+            let vd = self.view_data.as_ref().unwrap_or(&*d);
+            let prompting = self.mx.prompt_open();
             match self.mx.event(&e) {
                 Some(("main", i)) => match i {
                     0 => {
@@ -114,14 +233,106 @@ impl App for VizApp {
                     1 => {
                         self.view.reset();
                     },
+                    2 => {
+                        let current = String::from(self.view.filter_source().unwrap_or(""));
+                        self.mx.prompt("filter: ", &current, "filter");
+                    },
+                    3 => {
+                        if let Some(v) = self.prev_views.pop() { self.view = v; }
+                    },
+                    4 => {
+                        self.mx.prompt("add rule (hide|collapse|fold <regex>) or clear: ", "", "rules");
+                    },
+                    5 => {
+                        self.rules.fold_recursion = !self.rules.fold_recursion;
+                        save_rules(&self.rules, self.rules_read_only, &d, &mut self.mx, self.last_mouse);
+                        self.rules_dirty = true;
+                    },
+                    6 => {
+                        self.mx.prompt("group by (method|class|package <depth>): ", "", "group");
+                    },
+                    7 => {
+                        self.mx.popup(ColorScheme::all().iter().map(|c| c.name()).collect(), self.last_mouse, "colors");
+                    },
+                    8 => {
+                        self.mx.popup(self.themes.iter().map(|t| t.name.as_str()).collect(), self.last_mouse, "themes");
+                    },
+                    9 => {
+                        self.view.toggle_fit_depth();
+                    },
+                    10 => {
+                        self.view.toggle_thread_panel();
+                    },
+                    // active threads are counted from the call tree, which is built once loading finishes
+                    11 if d.loaded => {
+                        let prev = std::mem::replace(&mut self.view, Box::new(ConcurrencyView::new(vd)));
+                        self.prev_views.push(prev);
+                    },
+                    12 => {
+                        self.view.toggle_slowest_calls();
+                    },
                     _ => {}
                 },
-                Some((tag, i)) => self.view.menu_selection(&d, tag, i),
+                Some(("colors", i)) => {
+                    self.res.color_scheme = ColorScheme::all()[i];
+                },
+                Some(("themes", i)) => {
+                    self.pending_theme = Some(i);
+                },
+                Some((tag, i)) => self.view.menu_selection(vd, tag, i),
+                None => {}
+            }
+            match self.mx.take_prompt_result() {
+                Some(("group", text)) => {
+                    match Grouping::parse(text.trim()) {
+                        Ok(g) => {
+                            self.rules.grouping = g;
+                            save_rules(&self.rules, self.rules_read_only, &d, &mut self.mx, self.last_mouse);
+                            self.rules_dirty = true;
+                        },
+                        Err(e) => self.mx.popup(vec![&e], self.last_mouse, "err")
+                    }
+                },
+                Some(("rules", text)) => {
+                    if text.trim() == "clear" {
+                        self.rules.rules.clear();
+                    } else {
+                        match Rule::parse(&text) {
+                            Ok(r) => self.rules.rules.push(r),
+                            Err(e) => {
+                                self.mx.popup(vec![&e], self.last_mouse, "err");
+                                return false;
+                            }
+                        }
+                    }
+                    save_rules(&self.rules, self.rules_read_only, &d, &mut self.mx, self.last_mouse);
+                    self.rules_dirty = true;
+                },
+                Some((tag, text)) => self.view.prompt_result(vd, tag, &text, &mut self.mx),
                 None => {}
             }
-            if !self.view.event(&e, &d, &mut self.mx) {
-                if let WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Right, .. } = e {
-                    self.mx.popup(vec![ "load file", "reset view" ], self.last_mouse, "main");
+            if !prompting && !self.view.event(&e, vd, &mut self.mx) {
+                match e {
+                    WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Right, .. } => {
+                        self.mx.popup(vec![ "load file", "reset view", "filter calls...", "previous view", "frame rules...",
+                                            if self.rules.fold_recursion { "unfold recursion" } else { "fold recursion" },
+                                            "group by...", "color scheme...", "theme...", "fit depth", "threads",
+                                            if d.loaded { "concurrency timeline" } else { "concurrency timeline (still loading)" }, "slowest calls" ], self.last_mouse, "main");
+                    },
+                    WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Released, virtual_keycode: Some(VirtualKeyCode::Escape), .. }, .. } => {
+                        if let Some(v) = self.prev_views.pop() { self.view = v; }
+                    },
+                    _ => {}
+                }
+            }
+            if let Some(v) = self.view.next_view() {
+                let prev = std::mem::replace(&mut self.view, v);
+                self.prev_views.push(prev);
+            }
+            if let Some(f) = self.view.back_with_filter() {
+                if let Some(v) = self.prev_views.pop() {
+                    self.view = v;
+                    self.view.prompt_result(vd, "selection", &f, &mut self.mx);
                 }
             }
         }
@@ -129,14 +340,61 @@ impl App for VizApp {
     }
 }
 
+/// Rules saved next to a profile, and why any of them couldn't be read
+fn load_rules(profile: &Path) -> (RuleSet, Vec<String>) {
+    match RuleSet::load(RuleSet::path_for(profile)) {
+        Ok(res) => res,
+        Err(e) => (RuleSet::new(), vec![format!("could not load rules: {}", e)])
+    }
+}
+
+/// Save the rules next to the profile, if it came from a file. A rules file that couldn't be read is left alone
+fn save_rules(rules: &RuleSet, read_only: bool, data: &VizData, mx: &mut MenuContext, at: Point) {
+    if let Some(p) = data.path.as_ref() {
+        let path = RuleSet::path_for(p);
+        if read_only {
+            mx.popup(vec![&format!("not saving rules, {} has lines that couldn't be read", path.display())], at, "err");
+        } else if let Err(e) = rules.save(path) {
+            mx.popup(vec![&format!("could not save rules: {}", e)], at, "err");
+        }
+    }
+}
+
+/// `viz query <file> <expr>`: print every call matching a filter expression
+fn run_query(path: String, expr: &str) -> Result<(), Box<Error>> {
+    let filter = Filter::parse(expr)?;
+    let data = Arc::new(RwLock::new(VizData::new(path)));
+    VizData::load(data.clone())?;
+    let d = data.read().unwrap();
+    println!("thread|start|elapsed|depth|method");
+    for cr in d.calls.iter().filter(|cr| filter.matches(cr, &d)) {
+        println!("{}|{}|{}|{}|{}", cr.thread_id, cr.start_time, cr.elapsed_time, cr.depth,
+                 d.method_index.get(&cr.method_id).map(|m| m.as_str()).unwrap_or("?"));
+    }
+    Ok(())
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.get(0).map(|a| a == "query").unwrap_or(false) {
+        if args.len() < 3 {
+            eprintln!("usage: viz query <file> <expr>");
+            std::process::exit(2);
+        }
+        if let Err(e) = run_query(args[1].clone(), &args[2..].join(" ")) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     runic::init();
     let mut evl = EventsLoop::new();
     let mut window = WindowBuilder::new()
         .with_dimensions(512, 521)
         .with_title("Java Performance Visualizer")
         .build(&evl).expect("create window!");
+    let scale = window.hidpi_factor();
     let mut rx = RenderContext::new(&mut window).expect("create render context!");
-    let mut app = VizApp::init(&mut rx);
+    let mut app = VizApp::init(&mut rx, scale);
     app.run(&mut rx, &mut evl);
 }
@@ -1,5 +1,6 @@
 
 use view::Resources;
+use theme::to_color;
 
 use runic::*;
 use winit::*;
@@ -23,20 +24,34 @@ struct Menu {
     selected: isize
 }
 
+/// Single line text entry, shown along the bottom of the window
+struct Prompt {
+    name: &'static str,
+    label: String,
+    text: String
+}
+
 pub struct MenuContext {
     open_menus: Vec<Menu>,
-    active: isize
+    active: isize,
+    prompt: Option<Prompt>,
+    prompt_result: Option<(&'static str, String)>
 }
 
 impl MenuContext {
     pub fn new() -> MenuContext {
         MenuContext {
             open_menus: Vec::new(),
-            active: -1
+            active: -1,
+            prompt: None,
+            prompt_result: None
         }
     }
 
     pub fn event(&mut self, e: &WindowEvent) -> Option<(&'static str, usize)> {
+        if self.prompt.is_some() {
+            self.prompt_event(e);
+        }
         match e {
             &WindowEvent::CursorMoved { position: (x,y), .. } => {
                 let p = Point::xy(x as f32, y as f32);
@@ -77,33 +92,83 @@ impl MenuContext {
         }
     }
 
+    fn prompt_event(&mut self, e: &WindowEvent) {
+        let mut done = false;
+        let mut cancel = false;
+        if let Some(ref mut p) = self.prompt {
+            match e {
+                &WindowEvent::ReceivedCharacter(c) if !c.is_control() => p.text.push(c),
+                &WindowEvent::KeyboardInput { input: k, .. } if k.state == ElementState::Pressed => {
+                    match k.virtual_keycode {
+                        Some(VirtualKeyCode::Back) => { p.text.pop(); },
+                        Some(VirtualKeyCode::Return) => done = true,
+                        Some(VirtualKeyCode::Escape) => cancel = true,
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+        if done {
+            let p = self.prompt.take().unwrap();
+            self.prompt_result = Some((p.name, p.text));
+        } else if cancel {
+            self.prompt = None;
+        }
+    }
+
+    /// True while a prompt is open and capturing keyboard input
+    pub fn prompt_open(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Text entered into the last prompt, if it was just submitted
+    pub fn take_prompt_result(&mut self) -> Option<(&'static str, String)> {
+        self.prompt_result.take()
+    }
+
     pub fn paint(&mut self, rx: &mut RenderContext, res: &Resources) {
+        if let Some(ref p) = self.prompt {
+            let bounds = rx.bounds();
+            let ly = rx.new_text_layout(&format!("{}{}_", p.label, p.text), &res.font, bounds.w - res.px(8.0), bounds.h)
+                .expect("create text layout for prompt");
+            let h = ly.bounds().h + res.px(4.0);
+            let bnd = Rect::xywh(0.0, bounds.h - h, bounds.w, h);
+            rx.set_color(to_color(res.theme.menu_background));
+            rx.fill_rect(bnd);
+            rx.set_color(to_color(res.theme.menu_border));
+            rx.stroke_rect(bnd, res.px(2.0));
+            rx.set_color(to_color(res.theme.menu_text));
+            rx.draw_text_layout(Point::xy(res.px(4.0), bnd.y + res.px(2.0)), &ly);
+        }
         for (i,menu) in self.open_menus.iter_mut().enumerate() {
             if let None = menu.bounds {
-                let mut bnd = Rect::pnwh(menu.location, 4.0, 4.0);
-                bnd.x -= 4.0; bnd.y -= 4.0;
+                let pad = res.px(4.0);
+                let mut bnd = Rect::pnwh(menu.location, pad, pad);
+                bnd.x -= pad; bnd.y -= pad;
                 for &mut (ref s, ref mut oly) in menu.items.iter_mut() {
-                    let lyb = oly.get_or_insert_with(||rx.new_text_layout(&s, &res.font, 256.0, 64.0).expect("create text layout for menu"))
+                    let lyb = oly.get_or_insert_with(||rx.new_text_layout(&s, &res.font, res.px(256.0), res.px(64.0)).expect("create text layout for menu"))
                         .bounds();
-                    bnd.w = bnd.w.max(lyb.w+4.0);
+                    bnd.w = bnd.w.max(lyb.w+pad);
                     bnd.h += lyb.h;
                 }
                 menu.bounds = Some(bnd);
             }
             let bnd = menu.bounds.unwrap();
-            rx.set_color(Color::rgb(0.8, 0.8, 0.8));
+            rx.set_color(to_color(res.theme.menu_background));
             rx.fill_rect(bnd);
-            rx.set_color(Color::rgb(0.0, 0.0, 0.0));
-            rx.stroke_rect(bnd, 2.0);
-            let mut p = Point::xy(bnd.x+2.0, bnd.y+2.0);
+            rx.set_color(to_color(res.theme.menu_border));
+            rx.stroke_rect(bnd, res.px(2.0));
+            rx.set_color(to_color(res.theme.menu_text));
+            let mut p = Point::xy(bnd.x+res.px(2.0), bnd.y+res.px(2.0));
             let mut i = 0;
             for &(_, ref ly) in menu.items.iter() {
                 let lyr = ly.as_ref().unwrap();
                 let b = lyr.bounds();
                 if i == menu.selected {
-                    rx.set_color(Color::rgb(0.4, 0.45, 0.4));
-                    rx.fill_rect(Rect::xywh(p.x, p.y, bnd.w-4.0, b.h)); 
-                    rx.set_color(Color::rgb(0.0, 0.0, 0.0));
+                    rx.set_color(to_color(res.theme.menu_selected));
+                    rx.fill_rect(Rect::xywh(p.x, p.y, bnd.w-res.px(4.0), b.h)); 
+                    rx.set_color(to_color(res.theme.menu_text));
                 }
                 rx.draw_text_layout(p, lyr);
                 p.y += b.h;
@@ -122,6 +187,12 @@ impl MenuContext {
             selected: -1
         });
     }
-}
-
 
+    pub fn prompt(&mut self, label: &str, initial: &str, name: &'static str) {
+        self.prompt = Some(Prompt {
+            name: name,
+            label: String::from(label),
+            text: String::from(initial)
+        });
+    }
+}
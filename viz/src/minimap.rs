@@ -0,0 +1,121 @@
+use runic::*;
+
+use data::*;
+use view::Resources;
+use theme::*;
+
+/// Number of time slices the whole profile is divided into
+const BINS: usize = 512;
+
+/// Height of the strip at a hidpi factor of 1
+pub const MINIMAP_HEIGHT: f32 = 18.0;
+
+/// Overview of activity over the whole profile, drawn as a strip above the flame chart
+pub struct Minimap {
+    /// Average number of frames on the stack during each slice
+    bins: Vec<f32>,
+    max_bin: f32,
+    /// Threads, number of calls and end time the histogram was built for, so it is only rebuilt when they change
+    key: Option<(Vec<u32>, usize, u64)>
+}
+
+impl Minimap {
+    pub fn new() -> Minimap {
+        Minimap { bins: Vec::new(), max_bin: 0.0, key: None }
+    }
+
+    /// Rebuild the histogram if the threads or data changed. No threads means every thread
+    pub fn update(&mut self, data: &VizData, threads: &[u32]) {
+        let key = (threads.to_vec(), data.calls.len(), data.abs_end_time);
+        if self.key.as_ref() == Some(&key) { return; }
+        self.key = Some(key);
+        // positions in slices are worked out in f64, nanosecond times are too large for f32 to place precisely
+        let slice = (data.abs_end_time / BINS as u64).max(1) as f64;
+        // add how much of each slice every call covers, counting slices fully covered at their ends with a running sum
+        let mut bins = vec![0.0f32; BINS];
+        let mut covered = vec![0.0f32; BINS + 1];
+        for cr in data.calls.iter() {
+            if !threads.is_empty() && !threads.contains(&cr.thread_id) { continue; }
+            let start = cr.start_time as f64 / slice;
+            let end = (cr.start_time + cr.elapsed_time) as f64 / slice;
+            let (first, last) = ((start as usize).min(BINS - 1), (end as usize).min(BINS - 1));
+            if first == last {
+                bins[first] += (end - start) as f32;
+            } else {
+                bins[first] += ((first + 1) as f64 - start) as f32;
+                bins[last] += (end - last as f64) as f32;
+                covered[first + 1] += 1.0;
+                covered[last] -= 1.0;
+            }
+        }
+        let mut running = 0.0;
+        for i in 0..BINS {
+            running += covered[i];
+            bins[i] += running;
+        }
+        self.max_bin = bins.iter().cloned().fold(0.0, f32::max);
+        self.bins = bins;
+    }
+
+    /// Time at a horizontal position in the strip
+    pub fn time_at(&self, bounds: Rect, x: f32, data: &VizData) -> u64 {
+        (((x - bounds.x) / bounds.w).max(0.0).min(1.0) as f64 * data.abs_end_time as f64) as u64
+    }
+
+    /// Draw the histogram with the visible time range `(start, end)` outlined on top
+    pub fn paint(&self, rx: &mut RenderContext, res: &Resources, bounds: Rect, data: &VizData, (start, end): (f32, f32)) {
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(bounds);
+        if self.max_bin > 0.0 {
+            let w = bounds.w / self.bins.len() as f32;
+            rx.set_color(to_color(res.theme.panel_text));
+            for (i, &b) in self.bins.iter().enumerate() {
+                if b <= 0.0 { continue; }
+                let h = (b / self.max_bin).sqrt() * bounds.h;
+                rx.fill_rect(Rect::xywh(bounds.x + i as f32 * w, bounds.y + bounds.h - h, w.max(1.0), h));
+            }
+        }
+        let total = data.abs_end_time.max(1) as f32;
+        let x0 = bounds.x + (start / total).max(0.0).min(1.0) * bounds.w;
+        let x1 = bounds.x + (end / total).max(0.0).min(1.0) * bounds.w;
+        rx.set_color(to_color(res.theme.panel_border));
+        rx.stroke_rect(Rect::xywh(x0, bounds.y, (x1 - x0).max(res.px(2.0)), bounds.h), res.px(2.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    #[test]
+    fn bins_count_frames_on_the_stack() {
+        // 512 slices of 10ns: a root over slices 0 to 9, a call inside it over half of slice 2 and all of 3 and 4,
+        // and calls on another thread inside slice 100 and over the last slice
+        let d = profile(&[(1, 25, 25, 2, 2), (1, 0, 100, 1, 1), (2, 1002, 5, 1, 1), (2, 5110, 10, 1, 1)]);
+        let mut m = Minimap::new();
+        m.update(&d, &[]);
+        assert_eq!(m.bins.len(), BINS);
+        assert_eq!(&m.bins[..6], &[1.0, 1.0, 1.5, 2.0, 2.0, 1.0]);
+        assert_eq!(m.bins[9], 1.0);
+        assert_eq!(m.bins[10], 0.0);
+        assert_eq!(m.bins[100], 0.5);
+        assert_eq!(m.max_bin, 2.0);
+
+        m.update(&d, &[2]);
+        assert_eq!(m.bins[0], 0.0);
+        assert_eq!(m.bins[100], 0.5);
+        assert_eq!(m.bins[511], 1.0);
+    }
+
+    #[test]
+    fn time_at_keeps_nanoseconds() {
+        // at an hour of nanoseconds f32 can't tell apart times less than a few hundred nanoseconds apart
+        let d = profile(&[(1, 0, 3_600_000_000_001, 1, 1)]);
+        let bounds = Rect::xywh(0.0, 0.0, 1000.0, 10.0);
+        let m = Minimap::new();
+        assert_eq!(m.time_at(bounds, 1000.0, &d), 3_600_000_000_001);
+        assert_eq!(m.time_at(bounds, 500.0, &d), 1_800_000_000_000);
+        assert_eq!(m.time_at(bounds, -5.0, &d), 0);
+    }
+}
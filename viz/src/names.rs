@@ -0,0 +1,437 @@
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// A method name as written by the agent (javassist's `getLongName()`), split into its parts.
+/// Methods look like `com.acme.Foo$Bar.baz(java.lang.String,int)`, constructors like
+/// `com.acme.Foo(int)` and static initializers like `com.acme.Foo.<clinit>()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodName {
+    /// Package segments, empty for the default package
+    pub package: Vec<String>,
+    pub outer_class: String,
+    /// Nested classes from outermost to innermost, so `Foo$Bar$1` has `["Bar", "1"]`
+    pub inner_classes: Vec<String>,
+    /// Method name, or the class name for constructors
+    pub method: String,
+    pub params: Vec<String>,
+    pub is_constructor: bool,
+    pub is_static_init: bool
+}
+
+/// 64-bit FNV-1a hash of a name. Unlike method ids, which the agent hands out in the order methods are first
+/// called, this is the same for a method in every capture
+pub fn stable_hash(s: &str) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// Cut a plain name down to `max_chars` characters, ending it with an ellipsis if anything was removed
+pub fn fit_str(s: &str, max_chars: usize) -> String {
+    if s.chars().count() <= max_chars { return String::from(s); }
+    if max_chars == 0 { return String::new(); }
+    let mut res: String = s.chars().take(max_chars - 1).collect();
+    res.push('…');
+    res
+}
+
+/// Drop the package from a type name, keeping any array brackets: `java.lang.String[]` becomes `String[]`
+pub fn simple_type_name(t: &str) -> &str {
+    match t.rfind('.') {
+        Some(i) => &t[i+1..],
+        None => t
+    }
+}
+
+/// Split a class name into the outer class and its nested classes. Only a single `$` between two parts of
+/// the name separates classes, so generated names like `Foo$$Lambda` or `$Proxy12` stay whole
+fn split_nested(class: &str) -> Vec<String> {
+    let chars: Vec<char> = class.chars().collect();
+    let mut parts = vec![String::new()];
+    for (i, &c) in chars.iter().enumerate() {
+        let separates = c == '$' && i > 0 && chars[i-1] != '$' && chars.get(i+1).map(|&n| n != '$').unwrap_or(false);
+        if separates {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+impl MethodName {
+    /// Split up a method name. Anything without a parameter list isn't a method, it is kept as a plain name
+    /// with no class
+    pub fn parse(long_name: &str) -> MethodName {
+        let (head, params) = match long_name.find('(') {
+            Some(i) => (&long_name[..i], long_name[i+1..].trim_right_matches(')')),
+            None => return MethodName {
+                package: Vec::new(),
+                outer_class: String::new(),
+                inner_classes: Vec::new(),
+                method: String::from(long_name),
+                params: Vec::new(),
+                is_constructor: false,
+                is_static_init: false
+            }
+        };
+        let params: Vec<String> = params.split(',').map(|p| p.trim()).filter(|p| p.len() > 0).map(String::from).collect();
+        let mut segments: Vec<&str> = head.split('.').collect();
+
+        let mut is_static_init = false;
+        let mut is_constructor = false;
+        let method = match segments.last() {
+            Some(&"<clinit>") => { is_static_init = true; segments.pop() },
+            Some(&"<init>") => { is_constructor = true; segments.pop() },
+            _ => None
+        };
+        // the class is the first segment that looks like a type; packages are lower case by convention
+        let class_ix = segments.iter().position(|s| s.chars().next().map(|c| c.is_uppercase() || c == '$').unwrap_or(false))
+            .unwrap_or(segments.len().saturating_sub(if method.is_some() { 1 } else { 2 }));
+        let method = match method {
+            Some(m) => String::from(m),
+            None => if class_ix + 1 >= segments.len() {
+                // nothing after the class name means this is a constructor
+                is_constructor = true;
+                String::from(segments.last().cloned().unwrap_or(""))
+            } else {
+                segments[class_ix+1..].join(".")
+            }
+        };
+        let (package, class) = if class_ix < segments.len() {
+            (segments[..class_ix].iter().map(|s| String::from(*s)).collect(), segments[class_ix])
+        } else {
+            (segments.iter().map(|s| String::from(*s)).collect(), "")
+        };
+        let mut classes = split_nested(class).into_iter();
+        let outer_class = classes.next().unwrap_or_default();
+        let inner_classes: Vec<String> = classes.collect();
+        MethodName {
+            method: if is_constructor { inner_classes.last().unwrap_or(&outer_class).clone() } else { method },
+            package: package,
+            outer_class: outer_class,
+            inner_classes: inner_classes,
+            params: params,
+            is_constructor: is_constructor,
+            is_static_init: is_static_init
+        }
+    }
+
+    /// Class name without the package, using `.` between nested classes
+    pub fn class_name(&self) -> String {
+        let mut s = self.outer_class.clone();
+        for c in self.inner_classes.iter() {
+            s.push('.');
+            s.push_str(c);
+        }
+        s
+    }
+
+    /// Fully qualified class name, `com.acme.Foo$Bar`
+    pub fn qualified_class_name(&self) -> String {
+        let mut s = self.package_prefix(self.package.len());
+        if s.len() > 0 { s.push('.'); }
+        s.push_str(&self.outer_class);
+        for c in self.inner_classes.iter() {
+            s.push('$');
+            s.push_str(c);
+        }
+        s
+    }
+
+    /// The first `depth` segments of the package
+    pub fn package_prefix(&self, depth: usize) -> String {
+        self.package[..depth.min(self.package.len())].join(".")
+    }
+
+    /// Parameter list using simple type names, `(String,int)`
+    pub fn short_params(&self) -> String {
+        format!("({})", self.params.iter().map(|p| simple_type_name(p)).collect::<Vec<_>>().join(","))
+    }
+
+    /// A name that wasn't a method, with no class or parameters
+    pub fn is_plain(&self) -> bool {
+        self.outer_class.len() == 0 && self.package.len() == 0 && !self.is_constructor
+    }
+
+    /// Name of the method as it would be written in source, `Foo.bar` or `Foo.<init>`
+    pub fn short_name(&self) -> String {
+        if self.is_plain() {
+            self.method.clone()
+        } else if self.is_constructor {
+            format!("{}.<init>", self.class_name())
+        } else {
+            format!("{}.{}", self.class_name(), self.method)
+        }
+    }
+
+    /// Abbreviated name with single letter package segments: `c.a.Foo.bar(String,int)`
+    pub fn abbreviated(&self) -> String {
+        if self.is_plain() { return self.method.clone(); }
+        let mut s = String::new();
+        for p in self.package.iter() {
+            if let Some(c) = p.chars().next() { s.push(c); }
+            s.push('.');
+        }
+        s.push_str(&self.short_name());
+        s.push_str(&self.short_params());
+        s
+    }
+
+    /// Longest form of the name that fits in `max_chars` characters. Parameters are shortened then dropped,
+    /// then the package is abbreviated, then the package and class are dropped and finally the method
+    /// name is cut off with an ellipsis
+    pub fn fit(&self, max_chars: usize) -> String {
+        if self.is_plain() { return fit_str(&self.method, max_chars); }
+        let dropped_params = if self.params.len() > 0 { "(…)" } else { "()" };
+        let forms: [&Fn() -> String; 6] = [
+            &|| self.to_string(),
+            &|| format!("{}.{}{}", self.qualified_class_name(), self.method_or_init(), self.short_params()),
+            &|| format!("{}.{}{}", self.qualified_class_name(), self.method_or_init(), dropped_params),
+            &|| {
+                let abbr = self.abbreviated();
+                format!("{}{}", &abbr[..abbr.find('(').unwrap_or(abbr.len())], dropped_params)
+            },
+            &|| self.short_name(),
+            &|| self.method.clone()
+        ];
+        for f in forms.iter() {
+            let s = f();
+            if s.chars().count() <= max_chars { return s; }
+        }
+        fit_str(&self.method, max_chars)
+    }
+
+    fn method_or_init(&self) -> &str {
+        if self.is_constructor { "<init>" } else { &self.method }
+    }
+}
+
+/// Built in rules for names that change from run to run: Spring CGLIB proxies, Hibernate and javassist
+/// proxies, JDK lambdas and dynamic proxies and reflection accessors
+const BUILTIN_NORMALIZATIONS: &'static [(&'static str, &'static str)] = &[
+    (r"\$\$EnhancerBySpringCGLIB\$\$[0-9a-fA-F]+", "$$$$EnhancerBySpringCGLIB"),
+    (r"\$\$FastClassBySpringCGLIB\$\$[0-9a-fA-F]+", "$$$$FastClassBySpringCGLIB"),
+    (r"\$\$SpringCGLIB\$\$\d+", "$$$$SpringCGLIB"),
+    (r"\$HibernateProxy\$\w+", "$$HibernateProxy"),
+    (r"_\$\$_jvst[0-9a-fA-F]+_[0-9a-fA-F]+", "_$$$$_jvst"),
+    (r"_\$\$_javassist_\d+", "_$$$$_javassist"),
+    (r"\$\$Lambda(\$\d+)?/(0x)?[0-9a-fA-F]+", "$$$$Lambda"),
+    (r"\$Proxy\d+", "$$Proxy"),
+    (r"Generated(Serialization)?(Method|Constructor)Accessor\d+", "Generated$1${2}Accessor"),
+];
+
+/// Rewrites method names at load time so that generated classes get the same name in every run
+pub struct Normalizer {
+    rules: Vec<(Regex, String)>
+}
+
+impl Normalizer {
+    pub fn builtin() -> Normalizer {
+        Normalizer {
+            rules: BUILTIN_NORMALIZATIONS.iter()
+                .map(|&(re, rep)| (Regex::new(re).expect("builtin normalization regex"), String::from(rep))).collect()
+        }
+    }
+
+    /// Extra rules are kept per project next to the profile data
+    pub fn path_for<P: AsRef<Path>>(profile_path: P) -> PathBuf {
+        profile_path.as_ref().with_file_name(".viznames")
+    }
+
+    /// Parse a rule written as `<regex> => <replacement>`
+    pub fn parse_rule(s: &str) -> Result<(Regex, String), String> {
+        let mut parts = s.splitn(2, "=>");
+        let re = parts.next().unwrap_or("").trim();
+        let rep = match parts.next() {
+            Some(rep) if re.len() > 0 => rep.trim(),
+            _ => return Err(format!("expected '<regex> => <replacement>', got '{}'", s))
+        };
+        Ok((Regex::new(re).map_err(|e| e.to_string())?, String::from(rep)))
+    }
+
+    /// Built in rules followed by any from the project's rule file, one `<regex> => <replacement>` per line.
+    /// Rules that don't parse are left out and described in the returned messages
+    pub fn for_profile<P: AsRef<Path>>(profile_path: P) -> Result<(Normalizer, Vec<String>), io::Error> {
+        let mut n = Normalizer::builtin();
+        let mut errors = Vec::new();
+        let path = Normalizer::path_for(profile_path);
+        let f = match File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok((n, errors)),
+            Err(e) => return Err(e)
+        };
+        for (i, linep) in BufReader::new(f).lines().enumerate() {
+            let line = linep?;
+            if line.trim().len() == 0 || line.starts_with('#') { continue; }
+            match Normalizer::parse_rule(&line) {
+                Ok(rule) => n.rules.push(rule),
+                Err(e) => errors.push(format!("{} line {}: {}", path.display(), i + 1, e))
+            }
+        }
+        Ok((n, errors))
+    }
+
+    pub fn normalize(&self, name: &str) -> String {
+        let mut s = String::from(name);
+        for &(ref re, ref rep) in self.rules.iter() {
+            s = re.replace_all(&s, rep.as_str()).into_owned();
+        }
+        s
+    }
+
+    /// Normalize every name in a method index. Methods that end up with the same name share the lowest id,
+    /// the returned map gives the new id for every id that was merged away
+    pub fn apply(&self, index: HashMap<u32, String>) -> (HashMap<u32, String>, HashMap<u32, u32>) {
+        let mut ids: Vec<u32> = index.keys().cloned().collect();
+        ids.sort();
+        let mut by_name: HashMap<String, u32> = HashMap::new();
+        let mut remap = HashMap::new();
+        for id in ids {
+            let name = self.normalize(&index[&id]);
+            let keep = *by_name.entry(name).or_insert(id);
+            if keep != id {
+                remap.insert(id, keep);
+            }
+        }
+        (by_name.into_iter().map(|(name, id)| (id, name)).collect(), remap)
+    }
+}
+
+impl fmt::Display for MethodName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_plain() {
+            write!(f, "{}", self.method)
+        } else if self.is_constructor {
+            write!(f, "{}({})", self.qualified_class_name(), self.params.join(","))
+        } else {
+            write!(f, "{}.{}({})", self.qualified_class_name(), self.method, self.params.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_method() {
+        let n = MethodName::parse("com.acme.Foo$Bar.baz(java.lang.String,int)");
+        assert_eq!(n.package, vec!["com", "acme"]);
+        assert_eq!(n.outer_class, "Foo");
+        assert_eq!(n.inner_classes, vec!["Bar"]);
+        assert_eq!(n.method, "baz");
+        assert_eq!(n.params, vec!["java.lang.String", "int"]);
+        assert!(!n.is_constructor && !n.is_static_init);
+        assert_eq!(n.to_string(), "com.acme.Foo$Bar.baz(java.lang.String,int)");
+    }
+
+    #[test]
+    fn parse_constructors_and_initializers() {
+        let c = MethodName::parse("com.acme.Foo$Bar(int)");
+        assert!(c.is_constructor);
+        assert_eq!(c.method, "Bar");
+        assert_eq!(c.short_name(), "Foo.Bar.<init>");
+
+        let s = MethodName::parse("com.acme.Foo.<clinit>()");
+        assert!(s.is_static_init);
+        assert_eq!(s.method, "<clinit>");
+        assert_eq!(s.qualified_class_name(), "com.acme.Foo");
+
+        let d = MethodName::parse("Main.main(java.lang.String[])");
+        assert!(d.package.is_empty());
+        assert_eq!(d.short_params(), "(String[])");
+    }
+
+    #[test]
+    fn generated_class_names_round_trip() {
+        let l = MethodName::parse("com.acme.Foo$$Lambda.apply(java.lang.Object)");
+        assert_eq!(l.outer_class, "Foo$$Lambda");
+        assert!(l.inner_classes.is_empty());
+        assert_eq!(l.short_name(), "Foo$$Lambda.apply");
+
+        let p = MethodName::parse("com.sun.proxy.$Proxy12.invoke()");
+        assert_eq!(p.package, vec!["com", "sun", "proxy"]);
+        assert_eq!(p.outer_class, "$Proxy12");
+        assert_eq!(p.method, "invoke");
+
+        let h = MethodName::parse("com.acme.Foo$HibernateProxy$x1.get()");
+        assert_eq!(h.inner_classes, vec!["HibernateProxy", "x1"]);
+
+        for name in ["com.acme.Foo$$Lambda.apply(java.lang.Object)", "com.sun.proxy.$Proxy12.invoke()",
+                     "com.acme.Foo$$EnhancerBySpringCGLIB.save(int)", "com.acme.Foo$Bar$1.run()",
+                     "com.acme.Foo_$$_jvst12_3.get()", "com.acme.Foo$Bar(int)"].iter() {
+            assert_eq!(MethodName::parse(name).to_string(), *name);
+        }
+    }
+
+    #[test]
+    fn names_without_parameters_are_plain() {
+        for name in ["Foo", "", "com.acme.Foo"].iter() {
+            let n = MethodName::parse(name);
+            assert!(n.is_plain() && !n.is_constructor, "{}", name);
+            assert_eq!(n.to_string(), *name);
+            assert_eq!(n.short_name(), *name);
+        }
+        assert_eq!(MethodName::parse("com.acme.Foo").fit(8), "com.acm…");
+    }
+
+    #[test]
+    fn fit_drops_detail_in_order() {
+        let n = MethodName::parse("com.acme.Foo.bar(java.lang.String,int)");
+        assert_eq!(n.fit(100), "com.acme.Foo.bar(java.lang.String,int)");
+        assert_eq!(n.fit(30), "com.acme.Foo.bar(String,int)");
+        assert_eq!(n.fit(20), "com.acme.Foo.bar(…)");
+        assert_eq!(n.fit(15), "c.a.Foo.bar(…)");
+        assert_eq!(n.fit(10), "Foo.bar");
+        assert_eq!(n.fit(5), "bar");
+        assert_eq!(n.fit(2), "b…");
+        assert_eq!(n.fit(0), "");
+    }
+
+    #[test]
+    fn builtin_normalizations() {
+        let n = Normalizer::builtin();
+        assert_eq!(n.normalize("com.acme.Foo$$EnhancerBySpringCGLIB$$1a2b3c.run()"), "com.acme.Foo$$EnhancerBySpringCGLIB.run()");
+        assert_eq!(n.normalize("com.acme.Foo$$Lambda$12/0x0000000800c0b448.apply(java.lang.Object)"), "com.acme.Foo$$Lambda.apply(java.lang.Object)");
+        assert_eq!(n.normalize("com.sun.proxy.$Proxy42.invoke()"), "com.sun.proxy.$Proxy.invoke()");
+        assert_eq!(n.normalize("sun.reflect.GeneratedMethodAccessor17.invoke()"), "sun.reflect.GeneratedMethodAccessor.invoke()");
+        assert_eq!(n.normalize("com.acme.Foo.bar()"), "com.acme.Foo.bar()");
+    }
+
+    #[test]
+    fn apply_merges_into_lowest_id() {
+        let mut index = HashMap::new();
+        index.insert(3, String::from("com.sun.proxy.$Proxy7.get()"));
+        index.insert(5, String::from("com.sun.proxy.$Proxy12.get()"));
+        index.insert(4, String::from("com.acme.Foo.bar()"));
+        let (index, remap) = Normalizer::builtin().apply(index);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[&3], "com.sun.proxy.$Proxy.get()");
+        assert_eq!(remap.get(&5), Some(&3));
+        assert_eq!(remap.get(&4), None);
+    }
+
+    #[test]
+    fn bad_rules_are_skipped() {
+        let dir = ::std::env::temp_dir().join(format!("jiprofiler-names-{}", ::std::process::id()));
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let profile = dir.join("t.perf");
+        ::std::io::Write::write_all(&mut File::create(Normalizer::path_for(&profile)).unwrap(), "# comment\nno arrow here\n([ => x\nWorker\\d+ => Worker\n".as_bytes()).unwrap();
+        let (n, errors) = Normalizer::for_profile(&profile).unwrap();
+        ::std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("line 2"));
+        assert!(errors[1].contains("line 3"));
+        assert_eq!(n.normalize("com.acme.Worker12.run()"), "com.acme.Worker.run()");
+    }
+}
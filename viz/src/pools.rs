@@ -0,0 +1,166 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use data::*;
+
+/// Threads that do the same kind of work, like the workers of one executor
+pub struct ThreadPool {
+    pub name: String,
+    pub threads: Vec<u32>
+}
+
+/// Name of the pool a thread belongs to going by its name alone. Pools number their threads, so
+/// `pool-3-thread-7` belongs to `pool-3-thread-*` and `ForkJoinPool.commonPool-worker-12` to `ForkJoinPool.commonPool-worker-*`
+pub fn auto_pool_name(thread_name: &str) -> Option<String> {
+    let prefix = thread_name.trim_right_matches(|c: char| c.is_digit(10));
+    if prefix.len() == thread_name.len() || prefix.len() == 0 { return None; }
+    Some(format!("{}*", prefix))
+}
+
+/// User defined pools, `<thread name regex> => <pool name>` where the pool name can use the regex's capture groups
+pub struct PoolRules {
+    pub rules: Vec<(Regex, String)>
+}
+
+impl PoolRules {
+    pub fn new() -> PoolRules {
+        PoolRules { rules: Vec::new() }
+    }
+
+    /// Pool rules are saved per project, next to the profile data
+    pub fn path_for<P: AsRef<Path>>(profile_path: P) -> PathBuf {
+        profile_path.as_ref().with_file_name(".vizpools")
+    }
+
+    pub fn parse_rule(s: &str) -> Result<(Regex, String), String> {
+        let mut parts = s.splitn(2, "=>");
+        let re = parts.next().unwrap_or("").trim();
+        let name = parts.next().map(|n| n.trim()).unwrap_or("");
+        if re.len() == 0 || name.len() == 0 {
+            return Err(format!("expected '<regex> => <pool name>', got '{}'", s));
+        }
+        Ok((Regex::new(re).map_err(|e| e.to_string())?, String::from(name)))
+    }
+
+    /// Rules that don't parse are left out and described in the returned messages
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(PoolRules, Vec<String>), io::Error> {
+        let mut pr = PoolRules::new();
+        let mut errors = Vec::new();
+        let f = match File::open(path.as_ref()) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok((pr, errors)),
+            Err(e) => return Err(e)
+        };
+        for (i, linep) in BufReader::new(f).lines().enumerate() {
+            let line = linep?;
+            if line.trim().len() == 0 || line.starts_with('#') { continue; }
+            match PoolRules::parse_rule(&line) {
+                Ok(rule) => pr.rules.push(rule),
+                Err(e) => errors.push(format!("{} line {}: {}", path.as_ref().display(), i + 1, e))
+            }
+        }
+        Ok((pr, errors))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let mut f = File::create(path)?;
+        for &(ref re, ref name) in self.rules.iter() {
+            writeln!(f, "{} => {}", re.as_str(), name)?;
+        }
+        Ok(())
+    }
+
+    fn pool_name(&self, thread_name: &str) -> Option<String> {
+        for &(ref re, ref name) in self.rules.iter() {
+            if let Some(caps) = re.captures(thread_name) {
+                let mut s = String::new();
+                caps.expand(name, &mut s);
+                return Some(s);
+            }
+        }
+        auto_pool_name(thread_name)
+    }
+
+    /// Group the threads of a profile into pools. Pools found by name alone need at least two threads,
+    /// pools from the user's rules are kept even with one
+    pub fn pools(&self, data: &VizData) -> Vec<ThreadPool> {
+        let mut pools: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for &t in data.thread_ids.iter() {
+            let name = match data.threads.get(&t) {
+                Some(info) if info.name.len() > 0 => &info.name,
+                _ => continue
+            };
+            if let Some(pool) = self.pool_name(name) {
+                pools.entry(pool).or_insert_with(Vec::new).push(t);
+            }
+        }
+        pools.into_iter()
+            .filter(|&(_, ref threads)| threads.len() > 1 ||
+                    self.rules.iter().any(|&(ref re, _)| data.threads.get(&threads[0]).map(|i| re.is_match(&i.name)).unwrap_or(false)))
+            .map(|(name, threads)| ThreadPool { name: name, threads: threads })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_threads(names: &[(u32, &str)]) -> VizData {
+        let mut d = VizData::default();
+        for &(id, name) in names.iter() {
+            d.thread_ids.push(id);
+            d.threads.insert(id, ThreadInfo { id: id, name: String::from(name), group: String::from("main"), daemon: false, first_seen: 0, last_seen: 0 });
+        }
+        d
+    }
+
+    #[test]
+    fn pool_names_from_thread_names() {
+        assert_eq!(auto_pool_name("pool-3-thread-7"), Some(String::from("pool-3-thread-*")));
+        assert_eq!(auto_pool_name("ForkJoinPool.commonPool-worker-12"), Some(String::from("ForkJoinPool.commonPool-worker-*")));
+        assert_eq!(auto_pool_name("main"), None);
+        assert_eq!(auto_pool_name("42"), None);
+    }
+
+    #[test]
+    fn parse_pool_rules() {
+        let (re, name) = PoolRules::parse_rule("^http-nio-\\d+-exec-\\d+$ => http").unwrap();
+        assert!(re.is_match("http-nio-8080-exec-3"));
+        assert_eq!(name, "http");
+        assert!(PoolRules::parse_rule("no arrow").is_err());
+        assert!(PoolRules::parse_rule(" => name").is_err());
+        assert!(PoolRules::parse_rule("x =>").is_err());
+        assert!(PoolRules::parse_rule("([ => name").is_err());
+    }
+
+    #[test]
+    fn pools_from_names_and_rules() {
+        let d = with_threads(&[(1, "main"), (2, "pool-1-thread-1"), (3, "pool-1-thread-2"), (4, "Timer-0"), (5, "kafka-consumer")]);
+        let pools: Vec<(String, Vec<u32>)> = PoolRules::new().pools(&d).into_iter().map(|p| (p.name, p.threads)).collect();
+        assert_eq!(pools, vec![(String::from("pool-1-thread-*"), vec![2, 3])]);
+
+        // a rule keeps a pool of one thread, and can name it from the thread's name
+        let mut rules = PoolRules::new();
+        rules.rules.push(PoolRules::parse_rule("^(\\w+)-consumer$ => $1 consumers").unwrap());
+        let names: Vec<String> = rules.pools(&d).into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["kafka consumers", "pool-1-thread-*"]);
+    }
+
+    #[test]
+    fn bad_rules_are_skipped() {
+        let path = ::std::env::temp_dir().join(format!("jiprofiler-pools-{}.vizpools", ::std::process::id()));
+        ::std::io::Write::write_all(&mut File::create(&path).unwrap(), "^worker-\\d+$ => workers\nno arrow\n([ => broken\n".as_bytes()).unwrap();
+        let (rules, errors) = PoolRules::load(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(rules.rules.len(), 1);
+        assert_eq!(rules.rules[0].1, "workers");
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("line 2") && errors[1].contains("line 3"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::cmp::Reverse;
+
+use runic::*;
+
+use data::*;
+use names::fit_str;
+use view::Resources;
+use theme::*;
+use threads::thread_label;
+
+/// Number of calls listed
+pub const SLOWEST_COUNT: usize = 50;
+
+/// Width of the panel at a hidpi factor of 1
+const PANEL_WIDTH: f32 = 560.0;
+
+/// The `n` longest running calls of a method, or of every method, slowest first
+pub fn slowest_calls(data: &VizData, method: Option<u32>, n: usize) -> Vec<usize> {
+    let mut calls: Vec<usize> = match method {
+        Some(m) => data.invocations.get(&m).cloned().unwrap_or_default(),
+        None => (0..data.calls.len()).collect()
+    };
+    calls.sort_by_key(|&i| Reverse(data.calls[i].elapsed_time));
+    calls.truncate(n);
+    calls
+}
+
+/// Panel along the left side of the flame chart listing the slowest calls, for hunting down tail latency
+pub struct SlowestList {
+    pub visible: bool,
+    /// Method whose calls are listed, every method when there is none
+    method: Option<u32>,
+    calls: Vec<usize>,
+    /// Method, number of calls and whether loading had finished when the list was built
+    key: Option<(Option<u32>, usize, bool)>,
+    /// Rows scrolled past at the top of the list
+    scroll: usize,
+    bounds: Rect,
+    rows: Vec<(Rect, usize)>,
+    all_button: Rect
+}
+
+impl SlowestList {
+    pub fn new() -> SlowestList {
+        SlowestList {
+            visible: false,
+            method: None,
+            calls: Vec::new(),
+            key: None,
+            scroll: 0,
+            bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            rows: Vec::new(),
+            all_button: Rect::xywh(0.0, 0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Show the slowest calls of a method, or of every method
+    pub fn show(&mut self, method: Option<u32>) {
+        self.visible = true;
+        self.method = method;
+        self.scroll = 0;
+    }
+
+    fn update(&mut self, data: &VizData) {
+        // a method's invocations are only indexed once loading finishes
+        let key = (self.method, data.calls.len(), data.loaded);
+        if self.key == Some(key) { return; }
+        self.key = Some(key);
+        self.calls = slowest_calls(data, self.method, SLOWEST_COUNT);
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        self.visible && self.bounds.contains(p)
+    }
+
+    pub fn scroll_by(&mut self, rows: isize) {
+        self.scroll = (self.scroll as isize + rows).max(0) as usize;
+    }
+
+    /// The call that was clicked on
+    pub fn click(&mut self, p: Point) -> Option<usize> {
+        if self.method.is_some() && self.all_button.contains(p) {
+            self.show(None);
+            return None;
+        }
+        self.rows.iter().find(|&&(r, _)| r.contains(p)).map(|&(_, c)| c)
+    }
+
+    /// Draw the panel along the left side of `area`, highlighting the `selected` call if it is listed
+    pub fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData, area: Rect, selected: Option<usize>, mouse: Point) {
+        self.rows.clear();
+        if !self.visible { return; }
+        self.update(data);
+        let w = res.px(PANEL_WIDTH).min(area.w);
+        let line_h = res.line_height + res.px(4.0);
+        let max_rows = ((area.h - line_h - res.px(8.0)) / line_h).max(0.0) as usize;
+        self.scroll = self.scroll.min(self.calls.len().saturating_sub(max_rows));
+        let shown = self.calls.len().saturating_sub(self.scroll).min(max_rows);
+        self.bounds = Rect::xywh(area.x, area.y, w, (shown + 1) as f32 * line_h + res.px(8.0));
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(self.bounds);
+        rx.set_color(to_color(res.theme.panel_border));
+        rx.stroke_rect(self.bounds, res.px(1.0));
+
+        // header naming the method, with a button to go back to every method
+        let max_chars = ((w - res.px(8.0)) / res.char_width).max(0.0) as usize;
+        let y = self.bounds.y + res.px(4.0);
+        self.all_button = Rect::xywh(0.0, 0.0, 0.0, 0.0);
+        let title = match self.method {
+            Some(m) => {
+                self.all_button = Rect::xywh(self.bounds.x + w - res.char_width * 6.0 - res.px(4.0), y, res.char_width * 6.0, line_h);
+                rx.set_color(to_color(if self.all_button.contains(mouse) { res.theme.panel_highlight } else { res.theme.panel_background }));
+                rx.fill_rect(self.all_button);
+                rx.set_color(to_color(res.theme.panel_text));
+                rx.draw_text(Rect::xywh(self.all_button.x + res.char_width, y + res.px(2.0), self.all_button.w, line_h), "all", &res.font);
+                let name = data.method_names.get(&m).map(|n| n.short_name())
+                    .or_else(|| data.method_index.get(&m).cloned()).unwrap_or(String::from("?"));
+                fit_str(&format!("slowest calls of {}", name), max_chars.saturating_sub(7))
+            },
+            None => fit_str("slowest calls of all methods", max_chars)
+        };
+        rx.set_color(to_color(res.theme.panel_text));
+        rx.draw_text(Rect::xywh(self.bounds.x + res.px(4.0), y + res.px(2.0), w, line_h), &title, &res.font);
+
+        // one row per call: rank, duration, start time and thread, then the method when every method is listed
+        let mut y = y + line_h;
+        for (rank, &c) in self.calls.iter().enumerate().skip(self.scroll).take(max_rows) {
+            let cr = &data.calls[c];
+            let r = Rect::xywh(self.bounds.x + res.px(2.0), y, w - res.px(4.0), line_h);
+            if selected == Some(c) || r.contains(mouse) {
+                rx.set_color(to_color(res.theme.panel_highlight));
+                rx.fill_rect(r);
+            }
+            let mut line = format!("{:>3}. {:>10} at {:>10}  {}", rank + 1, format_duration(cr.elapsed_time),
+                                   format_duration(cr.start_time), thread_label(data, cr.thread_id));
+            if self.method.is_none() {
+                let name = data.method_names.get(&cr.method_id).map(|n| n.short_name())
+                    .or_else(|| data.method_index.get(&cr.method_id).cloned()).unwrap_or(String::from("?"));
+                line = format!("{}  {}", line, name);
+            }
+            rx.set_color(to_color(res.theme.panel_text));
+            rx.draw_text(Rect::xywh(r.x + res.px(2.0), r.y + res.px(2.0), r.w, r.h), &fit_str(&line, max_chars), &res.font);
+            self.rows.push((r, c));
+            y += line_h;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use runic::*;
+
+use data::*;
+use view::Resources;
+use theme::*;
+use names::fit_str;
+
+/// Most of the window the pane may take up before the outer frames are left off
+const MAX_HEIGHT_FRACTION: f32 = 0.4;
+
+/// Pane docked along the bottom of the flame chart listing the selected call and every call enclosing it
+pub struct CallStackPane {
+    bounds: Option<Rect>,
+    rows: Vec<(Rect, usize)>
+}
+
+impl CallStackPane {
+    pub fn new() -> CallStackPane {
+        CallStackPane { bounds: None, rows: Vec::new() }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        self.bounds.map(|b| b.contains(p)).unwrap_or(false)
+    }
+
+    /// Where the pane was last drawn, if it was
+    pub fn bounds(&self) -> Option<Rect> {
+        self.bounds
+    }
+
+    /// The frame that was clicked on
+    pub fn click(&self, p: Point) -> Option<usize> {
+        self.rows.iter().find(|&&(r, _)| r.contains(p)).map(|&(_, c)| c)
+    }
+
+    /// Draw the stack of `selected` along the bottom of `area`, or nothing when no call is selected
+    pub fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData, selected: Option<usize>, area: Rect, mouse: Point) {
+        self.rows.clear();
+        self.bounds = None;
+        let sel = match selected { Some(s) if s < data.calls.len() => s, _ => return };
+        let mut frames = data.ancestors(sel);
+        frames.push(sel);
+
+        let line_h = res.line_height + res.px(2.0);
+        let max_lines = ((area.h * MAX_HEIGHT_FRACTION - res.px(8.0)) / line_h).max(2.0) as usize;
+        // keep the innermost frames when they don't all fit
+        let skipped = (frames.len() + 1).saturating_sub(max_lines);
+        let h = (frames.len() - skipped + 1) as f32 * line_h + res.px(8.0);
+        let bnd = Rect::xywh(area.x, area.y + area.h - h, area.w, h);
+        self.bounds = Some(bnd);
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(bnd);
+        rx.set_color(to_color(res.theme.panel_border));
+        rx.stroke_rect(bnd, res.px(1.0));
+
+        let total_chars = ((bnd.w - res.px(8.0)) / res.char_width).max(0.0) as usize;
+        let numbers = |elapsed: &str, self_time: &str, of_parent: &str| format!(" {:>10} {:>10} {:>9}", elapsed, self_time, of_parent);
+        let name_chars = total_chars.saturating_sub(numbers("", "", "").chars().count());
+        let header = format!("{:<w$}{}", if skipped > 0 { format!("call stack ({} outer frames not shown)", skipped) } else { String::from("call stack") },
+                             numbers("elapsed", "self", "% parent"), w = name_chars);
+        rx.set_color(to_color(res.theme.panel_text));
+        rx.draw_text(Rect::xywh(bnd.x + res.px(4.0), bnd.y + res.px(4.0), bnd.w, line_h), &fit_str(&header, total_chars), &res.font);
+
+        let mut y = bnd.y + res.px(4.0) + line_h;
+        for (i, &c) in frames.iter().enumerate().skip(skipped) {
+            let cr = &data.calls[c];
+            let r = Rect::xywh(bnd.x + res.px(2.0), y, bnd.w - res.px(4.0), line_h);
+            if c == sel || r.contains(mouse) {
+                rx.set_color(to_color(res.theme.panel_highlight));
+                rx.fill_rect(r);
+            }
+            let name = data.method_names.get(&cr.method_id).map(|m| m.fit(name_chars.saturating_sub(i + 1)))
+                .or_else(|| data.method_index.get(&cr.method_id).map(|m| fit_str(m, name_chars.saturating_sub(i + 1))))
+                .unwrap_or(String::from("?"));
+            // parents are only known once the profile has finished loading
+            let of_parent = match data.parents.get(c).cloned().unwrap_or(None) {
+                Some(p) => format!("{:.1}%", cr.elapsed_time as f64 / data.calls[p].elapsed_time.max(1) as f64 * 100.0),
+                None => String::from("-")
+            };
+            let self_time = data.self_times.get(c).map(|&s| format_duration(s)).unwrap_or_default();
+            let line = format!("{:<w$}{}", format!("{}{}", " ".repeat(i + 1), name),
+                               numbers(&format_duration(cr.elapsed_time), &self_time, &of_parent), w = name_chars);
+            rx.set_color(to_color(res.theme.panel_text));
+            rx.draw_text(Rect::xywh(r.x + res.px(2.0), r.y, r.w, r.h), &line, &res.font);
+            self.rows.push((r, c));
+            y += line_h;
+        }
+    }
+}
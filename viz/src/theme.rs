@@ -0,0 +1,181 @@
+use std::io;
+use std::io::Read;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use runic::Color;
+use toml;
+
+use colors::Rgb;
+
+/// Colors and font for the whole interface. Theme files are TOML with any of these fields, colors are written
+/// as `[r, g, b]` arrays and anything left out is taken from the dark theme
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub name: String,
+    pub background: Rgb,
+    pub text: Rgb,
+    pub status_background: Rgb,
+    pub status_text: Rgb,
+    /// Colors blocks are picked from when coloring by method. Empty means the classic shaded palette
+    pub block_palette: Vec<Rgb>,
+    pub block_text: Rgb,
+    /// Breadcrumbs, badges and legends drawn over the chart
+    pub panel_background: Rgb,
+    pub panel_highlight: Rgb,
+    pub panel_text: Rgb,
+    pub panel_border: Rgb,
+    pub menu_background: Rgb,
+    pub menu_border: Rgb,
+    pub menu_selected: Rgb,
+    pub menu_text: Rgb,
+    pub tooltip_background: Rgb,
+    pub tooltip_text: Rgb,
+    pub tooltip_border: Rgb,
+    pub font_family: String,
+    pub font_size: f32
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            name: String::from("dark"),
+            background: (0.1, 0.1, 0.11),
+            text: (0.7, 0.7, 0.7),
+            status_background: (0.3, 0.3, 0.3),
+            status_text: (0.8, 0.8, 0.8),
+            block_palette: Vec::new(),
+            block_text: (0.0, 0.0, 0.0),
+            panel_background: (0.25, 0.25, 0.25),
+            panel_highlight: (0.4, 0.4, 0.4),
+            panel_text: (0.8, 0.8, 0.8),
+            panel_border: (0.6, 0.6, 0.6),
+            menu_background: (0.8, 0.8, 0.8),
+            menu_border: (0.0, 0.0, 0.0),
+            menu_selected: (0.4, 0.45, 0.4),
+            menu_text: (0.0, 0.0, 0.0),
+            tooltip_background: (0.3, 0.3, 0.3),
+            tooltip_text: (0.8, 0.8, 0.8),
+            tooltip_border: (0.6, 0.6, 0.6),
+            font_family: String::from("Consolas"),
+            font_size: 16.0
+        }
+    }
+}
+
+const BUILTIN_THEMES: &'static [&'static str] = &[
+r#"
+name = "dark"
+"#,
+r#"
+name = "light"
+background = [0.96, 0.96, 0.95]
+text = [0.2, 0.2, 0.2]
+status_background = [0.85, 0.85, 0.85]
+status_text = [0.1, 0.1, 0.1]
+block_palette = [[0.98, 0.75, 0.55], [0.98, 0.86, 0.55], [0.75, 0.9, 0.6], [0.6, 0.85, 0.9],
+                 [0.75, 0.75, 0.95], [0.92, 0.7, 0.85], [0.85, 0.8, 0.7], [0.7, 0.9, 0.8]]
+block_text = [0.05, 0.05, 0.05]
+panel_background = [0.88, 0.88, 0.88]
+panel_highlight = [0.78, 0.8, 0.85]
+panel_text = [0.1, 0.1, 0.1]
+panel_border = [0.5, 0.5, 0.5]
+menu_background = [1.0, 1.0, 1.0]
+menu_border = [0.4, 0.4, 0.4]
+menu_selected = [0.75, 0.82, 0.95]
+menu_text = [0.0, 0.0, 0.0]
+tooltip_background = [1.0, 1.0, 0.9]
+tooltip_text = [0.0, 0.0, 0.0]
+tooltip_border = [0.4, 0.4, 0.4]
+"#,
+r#"
+name = "high contrast"
+background = [0.0, 0.0, 0.0]
+text = [1.0, 1.0, 1.0]
+status_background = [0.0, 0.0, 0.0]
+status_text = [1.0, 1.0, 0.0]
+block_palette = [[1.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 0.5, 0.0], [0.5, 1.0, 0.0], [1.0, 0.6, 1.0], [1.0, 1.0, 1.0]]
+block_text = [0.0, 0.0, 0.0]
+panel_background = [0.0, 0.0, 0.0]
+panel_highlight = [0.0, 0.0, 0.6]
+panel_text = [1.0, 1.0, 1.0]
+panel_border = [1.0, 1.0, 1.0]
+menu_background = [0.0, 0.0, 0.0]
+menu_border = [1.0, 1.0, 1.0]
+menu_selected = [0.0, 0.0, 0.8]
+menu_text = [1.0, 1.0, 1.0]
+tooltip_background = [0.0, 0.0, 0.0]
+tooltip_text = [1.0, 1.0, 0.0]
+tooltip_border = [1.0, 1.0, 1.0]
+font_size = 18.0
+"#
+];
+
+pub fn to_color((r, g, b): Rgb) -> Color {
+    Color::rgb(r, g, b)
+}
+
+impl Theme {
+    pub fn parse(src: &str) -> Result<Theme, io::Error> {
+        toml::from_str(src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Theme, io::Error> {
+        let mut src = String::new();
+        File::open(path.as_ref())?.read_to_string(&mut src)?;
+        let mut t = Theme::parse(&src)?;
+        let named = src.parse::<toml::Value>().ok().map(|v| v.get("name").is_some()).unwrap_or(false);
+        if !named {
+            // unnamed themes are known by their file name
+            t.name = path.as_ref().file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or(t.name);
+        }
+        Ok(t)
+    }
+
+    /// User themes live in `~/.jiprofiler/themes/*.toml`
+    pub fn user_theme_dir() -> Option<PathBuf> {
+        ::std::env::var_os("HOME").or_else(|| ::std::env::var_os("USERPROFILE"))
+            .map(|h| PathBuf::from(h).join(".jiprofiler").join("themes"))
+    }
+
+    /// The built in themes followed by any the user has installed, and why any user themes couldn't be loaded
+    pub fn available() -> (Vec<Theme>, Vec<String>) {
+        let mut themes: Vec<Theme> = BUILTIN_THEMES.iter().map(|src| Theme::parse(src).expect("parse builtin theme")).collect();
+        let mut errors = Vec::new();
+        if let Some(entries) = Theme::user_theme_dir().and_then(|d| fs::read_dir(d).ok()) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().map(|e| e == "toml").unwrap_or(false) {
+                    match Theme::load(&path) {
+                        Ok(t) => themes.push(t),
+                        Err(e) => errors.push(format!("could not load theme {}: {}", path.display(), e))
+                    }
+                }
+            }
+        }
+        (themes, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn load_as(file: &str, src: &str) -> Theme {
+        let path = ::std::env::temp_dir().join(format!("{}-{}.toml", file, ::std::process::id()));
+        File::create(&path).unwrap().write_all(src.as_bytes()).unwrap();
+        let t = Theme::load(&path);
+        fs::remove_file(&path).unwrap();
+        t.unwrap()
+    }
+
+    #[test]
+    fn theme_names() {
+        let dark = Theme::default().name;
+        assert_eq!(load_as("mine", &format!("name = \"{}\"", dark)).name, dark);
+        assert_eq!(load_as("mine", "name = \"solar\"").name, "solar");
+        assert!(load_as("mine", "").name.starts_with("mine-"));
+    }
+}
@@ -0,0 +1,406 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use runic::*;
+
+use data::*;
+use names::fit_str;
+use view::Resources;
+use theme::*;
+use pools::*;
+
+/// Number of slices in each thread's sparkline
+const SPARK_BINS: usize = 48;
+
+/// Width of the panel at a hidpi factor of 1
+pub const PANEL_WIDTH: f32 = 360.0;
+
+/// Activity of one thread over the whole profile
+pub struct ThreadStats {
+    pub thread_id: u32,
+    pub calls: usize,
+    /// Time spent inside any call, counting only the outermost frames so nested calls aren't counted twice
+    pub busy_time: u64,
+    /// Fraction of each slice of the profile the thread was busy for
+    pub activity: Vec<f32>,
+    /// Start and end of every outermost call, in start order
+    pub busy_spans: Vec<(u64, u64)>
+}
+
+impl ThreadStats {
+    pub fn for_data(data: &VizData) -> Vec<ThreadStats> {
+        let mut stats: Vec<ThreadStats> = data.thread_ids.iter()
+            .map(|&t| ThreadStats { thread_id: t, calls: 0, busy_time: 0, activity: vec![0.0; SPARK_BINS], busy_spans: Vec::new() }).collect();
+        let index: HashMap<u32, usize> = data.thread_ids.iter().enumerate().map(|(i, &t)| (t, i)).collect();
+        let slice = (data.abs_end_time / SPARK_BINS as u64).max(1);
+        for (i, cr) in data.calls.iter().enumerate() {
+            let s = match index.get(&cr.thread_id) {
+                Some(&ix) => &mut stats[ix],
+                None => continue
+            };
+            s.calls += 1;
+            if data.parents.get(i).map(|p| p.is_some()).unwrap_or(false) { continue; }
+            s.busy_time += cr.elapsed_time;
+            let end = cr.start_time + cr.elapsed_time;
+            s.busy_spans.push((cr.start_time, end));
+            let mut b = (cr.start_time / slice) as usize;
+            while b < SPARK_BINS && b as u64 * slice < end {
+                let (b0, b1) = (b as u64 * slice, (b as u64 + 1) * slice);
+                s.activity[b] += (end.min(b1) - cr.start_time.max(b0)) as f32 / slice as f32;
+                b += 1;
+            }
+        }
+        for s in stats.iter_mut() {
+            s.busy_spans.sort();
+        }
+        stats
+    }
+
+    /// Time the thread was busy between two points in time
+    pub fn busy_between(&self, start: u64, end: u64) -> u64 {
+        self.busy_spans.iter().take_while(|&&(s, _)| s < end)
+            .map(|&(s, e)| e.min(end).saturating_sub(s.max(start))).sum()
+    }
+
+    /// Fraction of each of `slices` equal slices of the time between `start` and `end` the thread was busy for
+    pub fn busy_per_slice(&self, start: u64, end: u64, slices: usize) -> Vec<f32> {
+        let mut busy = vec![0.0f32; slices];
+        if end <= start || slices == 0 { return busy; }
+        let slice = (end - start) as f64 / slices as f64;
+        // spans never overlap, so they end in the same order they start in
+        let first = match self.busy_spans.binary_search_by_key(&start, |&(_, e)| e) { Ok(i) => i + 1, Err(i) => i };
+        for &(s, e) in self.busy_spans[first..].iter().take_while(|&&(s, _)| s < end) {
+            let (s, e) = ((s.max(start) - start) as f64, (e.min(end) - start) as f64);
+            let mut b = (s / slice) as usize;
+            while b < slices && (b as f64) * slice < e {
+                let (b0, b1) = (b as f64 * slice, (b + 1) as f64 * slice);
+                busy[b] += ((e.min(b1) - s.max(b0)) / slice) as f32;
+                b += 1;
+            }
+        }
+        busy
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ThreadSort {
+    Id,
+    Calls,
+    BusyTime
+}
+
+/// What a click in the panel picked
+pub enum PanelClick {
+    AllThreads,
+    Thread(u32),
+    Pool(Vec<u32>),
+    EditFilter,
+    EditPools,
+    Nothing
+}
+
+#[derive(Copy, Clone)]
+enum Row {
+    AllThreads,
+    /// Index into the pools
+    Pool(usize),
+    /// Index into the thread stats
+    Thread(usize)
+}
+
+/// Side panel listing every thread, used to pick which threads the flame chart shows
+pub struct ThreadPanel {
+    pub visible: bool,
+    /// Only threads whose name contains this are listed
+    pub name_filter: String,
+    sort: ThreadSort,
+    stats: Vec<ThreadStats>,
+    pool_rules: PoolRules,
+    /// Profile the pool rules were loaded for
+    pool_rules_for: Option<PathBuf>,
+    /// The pool rules file had lines that couldn't be read, saving would lose them
+    pool_rules_read_only: bool,
+    /// Problems loading the pool rules that haven't been shown yet
+    errors: Vec<String>,
+    pools: Vec<ThreadPool>,
+    /// Number of calls, end time and whether loading had finished when the stats were built
+    key: Option<(usize, u64, bool)>,
+    /// Rows scrolled past at the top of the list
+    scroll: usize,
+    bounds: Rect,
+    rows: Vec<(Rect, Row)>,
+    sort_buttons: Vec<(Rect, ThreadSort)>,
+    pools_button: Rect,
+    filter_button: Rect
+}
+
+impl ThreadPanel {
+    pub fn new() -> ThreadPanel {
+        ThreadPanel {
+            visible: false,
+            name_filter: String::new(),
+            sort: ThreadSort::Id,
+            stats: Vec::new(),
+            pool_rules: PoolRules::new(),
+            pool_rules_for: None,
+            pool_rules_read_only: false,
+            errors: Vec::new(),
+            pools: Vec::new(),
+            key: None,
+            scroll: 0,
+            bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            rows: Vec::new(),
+            sort_buttons: Vec::new(),
+            pools_button: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            filter_button: Rect::xywh(0.0, 0.0, 0.0, 0.0)
+        }
+    }
+
+    pub fn update(&mut self, data: &VizData) {
+        // the call tree is only built once loading finishes, before that nested calls look like busy time
+        let key = (data.calls.len(), data.abs_end_time, data.loaded);
+        if self.key == Some(key) { return; }
+        self.key = Some(key);
+        self.stats = ThreadStats::for_data(data);
+        if self.pool_rules_for != data.path {
+            self.pool_rules_for = data.path.clone();
+            let (rules, mut errors) = match data.path.as_ref() {
+                Some(p) => PoolRules::load(PoolRules::path_for(p))
+                    .unwrap_or_else(|e| (PoolRules::new(), vec![format!("could not load pool rules: {}", e)])),
+                None => (PoolRules::new(), Vec::new())
+            };
+            self.pool_rules = rules;
+            self.pool_rules_read_only = errors.len() > 0;
+            self.errors.append(&mut errors);
+        }
+        self.pools = self.pool_rules.pools(data);
+    }
+
+    /// Problems loading the pool rules since the last call
+    pub fn take_errors(&mut self) -> Vec<String> {
+        ::std::mem::replace(&mut self.errors, Vec::new())
+    }
+
+    /// Add a rule from the pool prompt, or remove them all with `clear`, and save the rules next to the profile
+    pub fn add_pool_rule(&mut self, data: &VizData, text: &str) -> Result<(), String> {
+        if text.trim() == "clear" {
+            self.pool_rules.rules.clear();
+        } else {
+            let rule = PoolRules::parse_rule(text)?;
+            self.pool_rules.rules.push(rule);
+        }
+        self.pools = self.pool_rules.pools(data);
+        match data.path.as_ref() {
+            Some(p) if self.pool_rules_read_only =>
+                Err(format!("not saving pool rules, {} has lines that couldn't be read", PoolRules::path_for(p).display())),
+            Some(p) => self.pool_rules.save(PoolRules::path_for(p)).map_err(|e| format!("could not save pool rules: {}", e)),
+            None => Ok(())
+        }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        self.visible && self.bounds.contains(p)
+    }
+
+    /// The pool made up of exactly these threads, if there is one
+    pub fn pool_for(&mut self, data: &VizData, threads: &[u32]) -> Option<usize> {
+        if threads.len() < 2 { return None; }
+        self.update(data);
+        self.pools.iter().position(|p| p.threads.len() == threads.len() && p.threads.iter().all(|t| threads.contains(t)))
+    }
+
+    /// Draw a lane in `bounds` with how many of a pool's threads were busy at each point between `start` and `end`
+    pub fn paint_pool_lane(&self, rx: &mut RenderContext, res: &Resources, bounds: Rect, pool: usize, (start, end): (u64, u64)) {
+        let pool = &self.pools[pool];
+        let columns = ((bounds.w / res.px(2.0)) as usize).max(1);
+        let mut busy = vec![0.0f32; columns];
+        for s in self.stats.iter().filter(|s| pool.threads.contains(&s.thread_id)) {
+            for (b, a) in busy.iter_mut().zip(s.busy_per_slice(start, end, columns)) { *b += a; }
+        }
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(bounds);
+        rx.set_color(to_color(res.theme.panel_highlight));
+        let w = bounds.w / columns as f32;
+        for (c, &b) in busy.iter().enumerate() {
+            let h = (b / pool.threads.len() as f32).min(1.0) * bounds.h;
+            if h > 0.0 { rx.fill_rect(Rect::xywh(bounds.x + c as f32 * w, bounds.y + bounds.h - h, w.max(1.0), h)); }
+        }
+        let average = busy.iter().sum::<f32>() / (columns * pool.threads.len()) as f32;
+        rx.set_color(to_color(res.theme.panel_text));
+        rx.draw_text(Rect::xywh(bounds.x + res.px(4.0), bounds.y + res.px(2.0), bounds.w, bounds.h),
+                     &format!("{} x{} {:.0}% busy", pool.name, pool.threads.len(), average * 100.0), &res.font);
+    }
+
+    pub fn scroll_by(&mut self, rows: isize) {
+        self.scroll = (self.scroll as isize + rows).max(0) as usize;
+    }
+
+    pub fn click(&mut self, p: Point) -> PanelClick {
+        if let Some(&(_, sort)) = self.sort_buttons.iter().find(|&&(r, _)| r.contains(p)) {
+            self.sort = sort;
+            return PanelClick::Nothing;
+        }
+        if self.filter_button.contains(p) {
+            return PanelClick::EditFilter;
+        }
+        if self.pools_button.contains(p) {
+            return PanelClick::EditPools;
+        }
+        match self.rows.iter().find(|&&(r, _)| r.contains(p)) {
+            Some(&(_, Row::AllThreads)) => PanelClick::AllThreads,
+            Some(&(_, Row::Pool(i))) => PanelClick::Pool(self.pools[i].threads.clone()),
+            Some(&(_, Row::Thread(i))) => PanelClick::Thread(self.stats[i].thread_id),
+            None => PanelClick::Nothing
+        }
+    }
+
+    /// Rows in the order they are listed with the filter applied: all threads, then pools, then single threads
+    fn listed(&self, data: &VizData) -> Vec<Row> {
+        let filter = self.name_filter.to_lowercase();
+        let pools = (0..self.pools.len()).filter(|&i| filter.len() == 0 || self.pools[i].name.to_lowercase().contains(&filter));
+        let stats = &self.stats;
+        let mut l: Vec<usize> = (0..stats.len())
+            .filter(|&i| filter.len() == 0 || thread_label(data, stats[i].thread_id).to_lowercase().contains(&filter)).collect();
+        match self.sort {
+            ThreadSort::Id => l.sort_by_key(|&i| stats[i].thread_id),
+            ThreadSort::Calls => l.sort_by_key(|&i| Reverse(stats[i].calls)),
+            ThreadSort::BusyTime => l.sort_by_key(|&i| Reverse(stats[i].busy_time))
+        }
+        Some(Row::AllThreads).into_iter().chain(pools.map(Row::Pool)).chain(l.into_iter().map(Row::Thread)).collect()
+    }
+
+    fn paint_sparkline(rx: &mut RenderContext, res: &Resources, r: Rect, activity: &[f32]) {
+        let sw = (r.w - res.px(8.0)) / activity.len() as f32;
+        let sh = res.px(8.0);
+        for (b, &a) in activity.iter().enumerate() {
+            let h = a.min(1.0) * sh;
+            if h <= 0.0 { continue; }
+            rx.fill_rect(Rect::xywh(r.x + res.px(4.0) + b as f32 * sw, r.y + r.h - res.px(1.0) - h, sw.max(1.0), h));
+        }
+    }
+
+    /// Draw the panel along the right side of `area`. Threads in `selected` are highlighted, none selected means all
+    /// threads are shown. Pools show how busy they were over `range`, the time span on screen
+    pub fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData, area: Rect, selected: &[u32],
+                 (range_start, range_end): (u64, u64), mouse: Point) {
+        self.rows.clear();
+        self.sort_buttons.clear();
+        if !self.visible { return; }
+        self.update(data);
+        let w = res.px(PANEL_WIDTH).min(area.w);
+        self.bounds = Rect::xywh(area.x + area.w - w, area.y, w, area.h);
+        let line_h = res.line_height + res.px(4.0);
+        rx.set_color(to_color(res.theme.panel_background));
+        rx.fill_rect(self.bounds);
+        rx.set_color(to_color(res.theme.panel_border));
+        rx.stroke_rect(self.bounds, res.px(1.0));
+
+        // header with the sort buttons and the filter
+        let mut x = self.bounds.x + res.px(4.0);
+        let y = self.bounds.y + res.px(2.0);
+        for &(label, sort) in [("id", ThreadSort::Id), ("calls", ThreadSort::Calls), ("busy", ThreadSort::BusyTime)].iter() {
+            let r = Rect::xywh(x, y, res.char_width * (label.len() + 2) as f32, line_h);
+            rx.set_color(to_color(if sort == self.sort { res.theme.panel_highlight } else { res.theme.panel_background }));
+            rx.fill_rect(r);
+            rx.set_color(to_color(res.theme.panel_text));
+            rx.draw_text(Rect::xywh(r.x + res.char_width, r.y + res.px(2.0), r.w, r.h), label, &res.font);
+            self.sort_buttons.push((r, sort));
+            x += r.w + res.px(4.0);
+        }
+        self.pools_button = Rect::xywh(x, y, res.char_width * 9.0, line_h);
+        rx.set_color(to_color(if self.pools_button.contains(mouse) { res.theme.panel_highlight } else { res.theme.panel_background }));
+        rx.fill_rect(self.pools_button);
+        rx.set_color(to_color(res.theme.panel_text));
+        rx.draw_text(Rect::xywh(x + res.char_width, y + res.px(2.0), self.pools_button.w, line_h), "pools...", &res.font);
+        x += self.pools_button.w + res.px(4.0);
+        self.filter_button = Rect::xywh(x, y, self.bounds.x + w - x - res.px(4.0), line_h);
+        rx.set_color(to_color(if self.filter_button.contains(mouse) { res.theme.panel_highlight } else { res.theme.panel_background }));
+        rx.fill_rect(self.filter_button);
+        rx.set_color(to_color(res.theme.panel_text));
+        let filter_label = if self.name_filter.len() > 0 { format!("filter: {}", self.name_filter) } else { String::from("filter...") };
+        rx.draw_text(Rect::xywh(self.filter_button.x + res.px(4.0), y + res.px(2.0), self.filter_button.w, line_h), &filter_label, &res.font);
+
+        // one row for all threads, then pools and single threads with their sparklines underneath the numbers
+        let row_h = line_h + res.px(10.0);
+        let max_rows = ((self.bounds.h - line_h - res.px(4.0)) / row_h).max(0.0) as usize;
+        let listed = self.listed(data);
+        self.scroll = self.scroll.min(listed.len().saturating_sub(max_rows));
+        let range = range_end.saturating_sub(range_start).max(1);
+        let mut y = y + line_h + res.px(4.0);
+        for &row in listed.iter().skip(self.scroll).take(max_rows) {
+            let r = Rect::xywh(self.bounds.x + res.px(2.0), y, w - res.px(4.0), row_h - res.px(2.0));
+            let is_selected = match row {
+                Row::AllThreads => selected.is_empty(),
+                Row::Pool(i) => selected.len() > 0 && self.pools[i].threads.iter().all(|t| selected.contains(t)),
+                Row::Thread(i) => selected.contains(&self.stats[i].thread_id)
+            };
+            rx.set_color(to_color(if is_selected || r.contains(mouse) { res.theme.panel_highlight } else { res.theme.panel_background }));
+            rx.fill_rect(r);
+            rx.set_color(to_color(res.theme.panel_text));
+            let max_chars = ((r.w - res.px(8.0)) / res.char_width).max(0.0) as usize;
+            let text_r = Rect::xywh(r.x + res.px(4.0), r.y + res.px(1.0), r.w, line_h);
+            match row {
+                Row::AllThreads => {
+                    rx.draw_text(text_r, &format!("all threads ({})", self.stats.len()), &res.font);
+                },
+                Row::Pool(i) => {
+                    // a pool's lane is its threads' activity averaged together
+                    let pool = &self.pools[i];
+                    let members: Vec<&ThreadStats> = self.stats.iter().filter(|s| pool.threads.contains(&s.thread_id)).collect();
+                    let busy: u64 = members.iter().map(|s| s.busy_between(range_start, range_end)).sum();
+                    let numbers = format!(" x{} {:.0}% busy", pool.threads.len(),
+                                          busy as f64 / (range * pool.threads.len() as u64) as f64 * 100.0);
+                    let name = fit_str(&pool.name, max_chars.saturating_sub(numbers.chars().count()));
+                    rx.draw_text(text_r, &format!("{}{}", name, numbers), &res.font);
+                    let mut activity = vec![0.0; SPARK_BINS];
+                    for s in members.iter() {
+                        for (a, &b) in activity.iter_mut().zip(s.activity.iter()) { *a += b / pool.threads.len() as f32; }
+                    }
+                    ThreadPanel::paint_sparkline(rx, res, r, &activity);
+                },
+                Row::Thread(i) => {
+                    let s = &self.stats[i];
+                    let numbers = format!(" {} calls {}", s.calls, format_duration(s.busy_time));
+                    let name = fit_str(&thread_label(data, s.thread_id), max_chars.saturating_sub(numbers.chars().count()));
+                    rx.draw_text(text_r, &format!("{}{}", name, numbers), &res.font);
+                    // sparkline of how busy the thread was over the whole profile
+                    ThreadPanel::paint_sparkline(rx, res, r, &s.activity);
+                }
+            }
+            self.rows.push((r, row));
+            y += row_h;
+        }
+    }
+}
+
+/// How a thread is named in the interface, with its id when it has a name
+pub fn thread_label(data: &VizData, thread_id: u32) -> String {
+    match data.threads.get(&thread_id) {
+        Some(t) if t.name.len() > 0 => format!("{} (#{})", t.name, thread_id),
+        _ => data.thread_name(thread_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    #[test]
+    fn busy_per_slice() {
+        // thread 1 is busy from 0 to 30 and from 50 to 60, with a nested call that mustn't count twice
+        let d = profile(&[(1, 5, 10, 2, 2), (1, 0, 30, 1, 1), (1, 50, 10, 1, 1)]);
+        let stats = ThreadStats::for_data(&d);
+        assert_eq!(stats[0].busy_spans, vec![(0, 30), (50, 60)]);
+        assert_eq!(stats[0].busy_between(20, 55), 15);
+        let busy = stats[0].busy_per_slice(0, 80, 4);
+        let expected = [1.0, 0.5, 0.5, 0.0];
+        for (b, e) in busy.iter().zip(expected.iter()) {
+            assert!((b - e).abs() < 1e-6, "{:?}", busy);
+        }
+        // spans that end before the range starts are skipped
+        assert_eq!(stats[0].busy_per_slice(30, 50, 2), vec![0.0, 0.0]);
+        assert!(stats[0].busy_per_slice(10, 10, 3).iter().all(|&b| b == 0.0));
+    }
+}
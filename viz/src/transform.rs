@@ -0,0 +1,412 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use data::*;
+use names::MethodName;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RuleAction {
+    /// Remove the frame, its callees move up to its parent
+    Hide,
+    /// Remove the frame and everything it calls, the time shows up as self time of the parent
+    Collapse,
+    /// Remove the frame if it is already inside a call of the same method
+    Fold
+}
+
+pub struct Rule {
+    pub action: RuleAction,
+    pub pattern: Regex
+}
+
+impl Rule {
+    /// Parse a rule written as `<hide|collapse|fold> <method regex>`
+    pub fn parse(s: &str) -> Result<Rule, String> {
+        let s = s.trim();
+        let (action, pattern) = match s.find(char::is_whitespace) {
+            Some(i) => (&s[..i], s[i..].trim()),
+            None => return Err(format!("expected '<hide|collapse|fold> <regex>', got '{}'", s))
+        };
+        Ok(Rule {
+            action: match action {
+                "hide" => RuleAction::Hide,
+                "collapse" => RuleAction::Collapse,
+                "fold" => RuleAction::Fold,
+                _ => return Err(format!("unknown rule action '{}'", action))
+            },
+            pattern: Regex::new(pattern).map_err(|e| e.to_string())?
+        })
+    }
+
+    pub fn to_string(&self) -> String {
+        format!("{} {}", match self.action {
+            RuleAction::Hide => "hide",
+            RuleAction::Collapse => "collapse",
+            RuleAction::Fold => "fold"
+        }, self.pattern.as_str())
+    }
+}
+
+/// Level at which frames are grouped together. Nested frames in the same group are merged into one
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Grouping {
+    Method,
+    Class,
+    /// Package prefix with this many segments
+    Package(usize)
+}
+
+impl Grouping {
+    /// Parse `method`, `class` or `package <depth>`
+    pub fn parse(s: &str) -> Result<Grouping, String> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("method"), None) => Ok(Grouping::Method),
+            (Some("class"), None) => Ok(Grouping::Class),
+            (Some("package"), None) => Ok(Grouping::Package(2)),
+            (Some("package"), Some(n)) => n.parse::<usize>().map(Grouping::Package).map_err(|e| e.to_string()),
+            _ => Err(format!("expected 'method', 'class' or 'package <depth>', got '{}'", s))
+        }
+    }
+
+    /// Written the way `parse` reads it
+    pub fn to_string(&self) -> String {
+        match *self {
+            Grouping::Method => String::from("method"),
+            Grouping::Class => String::from("class"),
+            Grouping::Package(depth) => format!("package {}", depth)
+        }
+    }
+
+    fn key(&self, name: &MethodName) -> String {
+        // names that aren't methods have no class or package to group by
+        if name.is_plain() { return name.to_string(); }
+        match *self {
+            Grouping::Method => name.to_string(),
+            Grouping::Class => {
+                let mut s = name.package_prefix(name.package.len());
+                if s.len() > 0 { s.push('.'); }
+                s.push_str(&name.outer_class);
+                s
+            },
+            Grouping::Package(depth) => {
+                let s = name.package_prefix(depth);
+                if s.len() > 0 { s } else { String::from("(default package)") }
+            }
+        }
+    }
+}
+
+/// User defined rules for removing clutter frames from the call tree
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+    /// Fold recursion of every method, not just those matched by a fold rule
+    pub fold_recursion: bool,
+    pub grouping: Grouping
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet { rules: Vec::new(), fold_recursion: false, grouping: Grouping::Method }
+    }
+
+    /// Rules are saved per project, next to the profile data
+    pub fn path_for<P: AsRef<Path>>(profile_path: P) -> PathBuf {
+        profile_path.as_ref().with_file_name(".vizrules")
+    }
+
+    /// One rule per line, plus `set <option>` lines for the options that apply to every method.
+    /// Lines that don't parse are left out and described in the returned messages
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(RuleSet, Vec<String>), io::Error> {
+        let mut rs = RuleSet::new();
+        let mut errors = Vec::new();
+        let f = match File::open(path.as_ref()) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok((rs, errors)),
+            Err(e) => return Err(e)
+        };
+        for (i, linep) in BufReader::new(f).lines().enumerate() {
+            let line = linep?;
+            if line.trim().len() == 0 || line.starts_with('#') { continue; }
+            let mut words = line.split_whitespace();
+            let res = if words.next() == Some("set") {
+                match words.next() {
+                    Some("fold_recursion") => { rs.fold_recursion = true; Ok(()) },
+                    Some("group") => Grouping::parse(&words.collect::<Vec<_>>().join(" ")).map(|g| rs.grouping = g),
+                    _ => Err(format!("unknown option in '{}'", line))
+                }
+            } else {
+                Rule::parse(&line).map(|r| rs.rules.push(r))
+            };
+            if let Err(e) = res {
+                errors.push(format!("{} line {}: {}", path.as_ref().display(), i + 1, e));
+            }
+        }
+        Ok((rs, errors))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let mut f = File::create(path)?;
+        if self.fold_recursion {
+            writeln!(f, "set fold_recursion")?;
+        }
+        if self.grouping != Grouping::Method {
+            writeln!(f, "set group {}", self.grouping.to_string())?;
+        }
+        for r in self.rules.iter() {
+            writeln!(f, "{}", r.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn action_for(&self, name: &str) -> Option<RuleAction> {
+        self.rules.iter().find(|r| r.pattern.is_match(name)).map(|r| r.action)
+    }
+
+    /// Build a copy of the data with the rules applied and depths recomputed, or None if there is nothing to do
+    pub fn apply(&self, data: &VizData) -> Option<VizData> {
+        if (self.rules.len() == 0 && !self.fold_recursion && self.grouping == Grouping::Method) || !data.loaded { return None; }
+
+        let actions: Vec<Option<RuleAction>> = data.calls.iter()
+            .map(|cr| data.method_index.get(&cr.method_id).and_then(|m| self.action_for(m))).collect();
+
+        // every group gets a new id that stands in for the method id
+        let mut groups: HashMap<String, u32> = HashMap::new();
+        let mut group_of: HashMap<u32, u32> = HashMap::new();
+        if self.grouping != Grouping::Method {
+            for (&id, name) in data.method_names.iter() {
+                let next_id = groups.len() as u32;
+                let g = *groups.entry(self.grouping.key(name)).or_insert(next_id);
+                group_of.insert(id, g);
+            }
+        }
+        let group = |i: usize| group_of.get(&data.calls[i].method_id).cloned();
+
+        // visit parents before their children so each call can see what happened above it
+        let mut order: Vec<usize> = (0..data.calls.len()).collect();
+        order.sort_by_key(|&i| (data.calls[i].thread_id, data.calls[i].start_time, data.calls[i].depth));
+
+        let mut removed = vec![false; data.calls.len()];
+        let mut collapsed = vec![false; data.calls.len()];
+        // nearest ancestor that survives, and the new depth of each surviving call
+        let mut visible_parent: Vec<Option<usize>> = vec![None; data.calls.len()];
+        let mut depth = vec![0u32; data.calls.len()];
+        // deepest level of recursion folded into each call
+        let mut recursion = vec![0u32; data.calls.len()];
+        for &i in order.iter() {
+            let parent = data.parents[i];
+            if parent.map(|p| collapsed[p]).unwrap_or(false) {
+                collapsed[i] = true;
+                removed[i] = true;
+                continue;
+            }
+            let vp = match parent { Some(p) => if removed[p] { visible_parent[p] } else { Some(p) }, None => None };
+            visible_parent[i] = vp;
+            let action = match actions[i] {
+                None if self.fold_recursion => Some(RuleAction::Fold),
+                a => a
+            };
+            match action {
+                Some(RuleAction::Hide) => removed[i] = true,
+                Some(RuleAction::Collapse) => {
+                    collapsed[i] = true;
+                    // a root frame has nowhere to collapse into, so it stays and loses its callees
+                    removed[i] = vp.is_some();
+                },
+                Some(RuleAction::Fold) => {
+                    let method_id = data.calls[i].method_id;
+                    let mut cur = vp;
+                    while let Some(a) = cur {
+                        if data.calls[a].method_id == method_id {
+                            removed[i] = true;
+                            // count the recursive calls between here and the call that absorbs this one
+                            let mut level = 0;
+                            let mut p = Some(i);
+                            while let Some(c) = p {
+                                if c == a { break; }
+                                if data.calls[c].method_id == method_id { level += 1; }
+                                p = data.parents[c];
+                            }
+                            recursion[a] = recursion[a].max(level);
+                            break;
+                        }
+                        cur = visible_parent[a];
+                    }
+                },
+                None => {}
+            }
+            if !removed[i] && self.grouping != Grouping::Method {
+                // nested frames of the same group merge into the outermost one
+                removed[i] = vp.map(|p| group(p) == group(i)).unwrap_or(false);
+            }
+            depth[i] = vp.map(|p| depth[p] + 1).unwrap_or(1);
+        }
+
+        let mut vd = VizData::default();
+        vd.calls = data.calls.iter().enumerate().filter(|&(i, _)| !removed[i])
+            .map(|(i, cr)| CallRecord { depth: depth[i], .. *cr }).collect();
+        if self.grouping == Grouping::Method {
+            vd.method_index = data.method_index.clone();
+            vd.method_names = data.method_names.clone();
+        } else {
+            for cr in vd.calls.iter_mut() {
+                cr.method_id = group_of.get(&cr.method_id).cloned().unwrap_or(u32::max_value());
+            }
+            vd.method_index = groups.into_iter().map(|(name, id)| (id, name)).collect();
+        }
+        vd.thread_ids = data.thread_ids.clone();
+        vd.threads = data.threads.clone();
+        vd.abs_end_time = data.abs_end_time;
+        vd.path = data.path.clone();
+        vd.recursion_depth = recursion.into_iter().enumerate().filter(|&(i, _)| !removed[i]).map(|(_, r)| r).collect();
+        vd.build_indices();
+        Some(vd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::tests::profile;
+
+    fn named(mut d: VizData, names: &[(u32, &str)]) -> VizData {
+        for &(id, name) in names.iter() {
+            d.method_index.insert(id, String::from(name));
+            d.method_names.insert(id, MethodName::parse(name));
+        }
+        d
+    }
+
+    fn temp_rules(name: &str) -> PathBuf {
+        ::std::env::temp_dir().join(format!("jiprofiler-{}-{}.vizrules", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn parse_grouping() {
+        assert_eq!(Grouping::parse("method"), Ok(Grouping::Method));
+        assert_eq!(Grouping::parse(" class "), Ok(Grouping::Class));
+        assert_eq!(Grouping::parse("package"), Ok(Grouping::Package(2)));
+        assert_eq!(Grouping::parse("package 3"), Ok(Grouping::Package(3)));
+        assert!(Grouping::parse("package x").is_err());
+        assert!(Grouping::parse("class 2").is_err());
+        assert!(Grouping::parse("").is_err());
+        for g in [Grouping::Method, Grouping::Class, Grouping::Package(4)].iter() {
+            assert_eq!(Grouping::parse(&g.to_string()), Ok(*g));
+        }
+    }
+
+    #[test]
+    fn group_by_class_and_package() {
+        let d = named(profile(&[(1, 20, 10, 3, 3), (1, 10, 40, 2, 2), (1, 0, 100, 1, 1)]),
+                      &[(1, "com.acme.a.Foo.run()"), (2, "com.acme.a.Foo.help()"), (3, "com.acme.b.Bar.x()")]);
+        let mut rs = RuleSet::new();
+        rs.grouping = Grouping::Class;
+        let vd = rs.apply(&d).unwrap();
+        let mut names: Vec<(u32, &str)> = vd.calls.iter().map(|cr| (cr.depth, vd.method_index[&cr.method_id].as_str())).collect();
+        names.sort();
+        assert_eq!(names, vec![(1, "com.acme.a.Foo"), (2, "com.acme.b.Bar")]);
+
+        rs.grouping = Grouping::Package(2);
+        let vd = rs.apply(&d).unwrap();
+        assert_eq!(vd.calls.len(), 1);
+        assert_eq!(vd.method_index[&vd.calls[0].method_id], "com.acme");
+    }
+
+    #[test]
+    fn grouping_is_saved() {
+        let path = temp_rules("group");
+        let mut rs = RuleSet::new();
+        rs.grouping = Grouping::Package(3);
+        rs.rules.push(Rule::parse("hide ^java\\.").unwrap());
+        rs.save(&path).unwrap();
+        let (loaded, errors) = RuleSet::load(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(loaded.grouping, Grouping::Package(3));
+        assert_eq!(loaded.rules.len(), 1);
+        assert_eq!(loaded.rules[0].to_string(), "hide ^java\\.");
+    }
+
+    #[test]
+    fn fold_recursion() {
+        // run -> run -> help -> run, all folded into the outermost run
+        let d = profile(&[(1, 30, 10, 1, 4), (1, 20, 60, 2, 3), (1, 10, 80, 1, 2), (1, 0, 100, 1, 1)]);
+        let mut rs = RuleSet::new();
+        rs.fold_recursion = true;
+        let vd = rs.apply(&d).unwrap();
+        let calls: Vec<(u32, u32)> = vd.calls.iter().map(|cr| (cr.method_id, cr.depth)).collect();
+        assert_eq!(calls, vec![(2, 2), (1, 1)]);
+        assert_eq!(vd.recursion_depth, vec![0, 2]);
+    }
+
+    #[test]
+    fn fold_recursion_is_saved() {
+        let path = temp_rules("fold");
+        let mut rs = RuleSet::new();
+        rs.fold_recursion = true;
+        rs.save(&path).unwrap();
+        let (loaded, errors) = RuleSet::load(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert!(errors.is_empty());
+        assert!(loaded.fold_recursion);
+        assert_eq!(loaded.grouping, Grouping::Method);
+        assert!(loaded.rules.is_empty());
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let path = temp_rules("bad");
+        ::std::io::Write::write_all(&mut File::create(&path).unwrap(),
+            "hide ^java\\.\nshow Foo\nset group nothing\ncollapse Bar$\nset fold_recursion\n".as_bytes()).unwrap();
+        let (loaded, errors) = RuleSet::load(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("line 2"));
+        assert!(errors[1].contains("line 3"));
+        let rules: Vec<String> = loaded.rules.iter().map(|r| r.to_string()).collect();
+        assert_eq!(rules, vec!["hide ^java\\.", "collapse Bar$"]);
+        assert!(loaded.fold_recursion);
+        assert!(RuleSet::load(temp_rules("missing")).unwrap().1.is_empty());
+    }
+
+    /// run calls helper which calls leaf, and run then calls leaf directly
+    fn nested() -> VizData {
+        named(profile(&[(1, 20, 10, 3, 3), (1, 10, 50, 2, 2), (1, 70, 10, 3, 2), (1, 0, 100, 1, 1)]),
+              &[(1, "com.acme.Foo.run()"), (2, "com.acme.Foo.helper()"), (3, "com.acme.Foo.leaf()")])
+    }
+
+    #[test]
+    fn hide_moves_callees_up() {
+        let mut rs = RuleSet::new();
+        rs.rules.push(Rule::parse("hide helper").unwrap());
+        let vd = rs.apply(&nested()).unwrap();
+        let calls: Vec<(u32, u32)> = vd.calls.iter().map(|cr| (cr.method_id, cr.depth)).collect();
+        assert_eq!(calls, vec![(3, 2), (3, 2), (1, 1)]);
+        assert_eq!(vd.parents, vec![Some(2), Some(2), None]);
+        assert_eq!(vd.self_times[2], 80);
+    }
+
+    #[test]
+    fn collapse_keeps_time_in_the_parent() {
+        let mut rs = RuleSet::new();
+        rs.rules.push(Rule::parse("collapse helper").unwrap());
+        let vd = rs.apply(&nested()).unwrap();
+        let calls: Vec<(u32, u32)> = vd.calls.iter().map(|cr| (cr.method_id, cr.depth)).collect();
+        assert_eq!(calls, vec![(3, 2), (1, 1)]);
+        // helper and the leaf it called are now part of run's own time
+        assert_eq!(vd.self_times[1], 90);
+
+        // a collapsed root has nowhere to go, so it stays without its callees
+        let mut rs = RuleSet::new();
+        rs.rules.push(Rule::parse("collapse run").unwrap());
+        let vd = rs.apply(&nested()).unwrap();
+        assert_eq!(vd.calls.len(), 1);
+        assert_eq!(vd.calls[0].method_id, 1);
+        assert_eq!(vd.self_times[0], 100);
+    }
+}
@@ -6,62 +6,502 @@ use winit::*;
 
 use data::*;
 use menu::*;
+use filter::*;
+use aggregate::*;
+use names::fit_str;
+use colors::*;
+use theme::*;
+use minimap::*;
+use threads::*;
+use inspector::paint_instant;
+use stackpane::*;
+use invocations::*;
+use slowest::*;
+use distribution::DistributionView;
+
+/// Height of one row of blocks and the distance between rows, at a hidpi factor of 1
+pub const ROW_HEIGHT: f32 = 32.0;
+pub const ROW_STRIDE: f32 = 34.0;
+
+/// Fonts tried in order when the theme's font is not installed
+const FONT_FALLBACKS: &'static [&'static str] = &[
+    "Consolas", "DejaVu Sans Mono", "Liberation Mono", "Menlo", "Noto Mono", "monospace"
+];
 
 pub struct Resources {
-    pub font: Font
+    pub font: Font,
+    /// Average width of one character, used to fit labels without laying them out
+    pub char_width: f32,
+    /// Height of one line of text, which is also the height of the status bar
+    pub line_height: f32,
+    pub color_scheme: ColorScheme,
+    pub theme: Theme,
+    /// The window's hidpi factor, every size in the layout is multiplied by it
+    pub scale: f32
 }
 
 impl Resources {
-    pub fn init(rx: &mut RenderContext) -> Result<Resources,Box<Error>> {
+    pub fn init(rx: &mut RenderContext, theme: Theme, scale: f32) -> Result<Resources,Box<Error>> {
+        let (font, char_width, line_height) = Resources::load_font(rx, &theme, scale)?;
         Ok(Resources {
-            font: rx.new_font("Consolas", 16.0, FontWeight::Regular, FontStyle::Normal)?
+            font: font,
+            char_width: char_width,
+            line_height: line_height,
+            color_scheme: ColorScheme::MethodHash,
+            theme: theme,
+            scale: scale
         })
     }
+
+    fn load_font(rx: &mut RenderContext, theme: &Theme, scale: f32) -> Result<(Font, f32, f32), Box<Error>> {
+        let mut font = Err(From::from("no fonts to try"));
+        for family in Some(theme.font_family.as_str()).into_iter().chain(FONT_FALLBACKS.iter().cloned()) {
+            font = rx.new_font(family, theme.font_size * scale, FontWeight::Regular, FontStyle::Normal);
+            if font.is_ok() { break; }
+        }
+        let font = font?;
+        let sample = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.()";
+        let sample_bounds = rx.new_text_layout(sample, &font, 4096.0 * scale, 64.0 * scale)?.bounds();
+        Ok((font, sample_bounds.w / sample.len() as f32, sample_bounds.h))
+    }
+
+    pub fn set_theme(&mut self, rx: &mut RenderContext, theme: Theme) -> Result<(), Box<Error>> {
+        let (font, char_width, line_height) = Resources::load_font(rx, &theme, self.scale)?;
+        self.font = font;
+        self.char_width = char_width;
+        self.line_height = line_height;
+        self.theme = theme;
+        Ok(())
+    }
+
+    /// Reload the font at the size for a new hidpi factor
+    pub fn set_scale(&mut self, rx: &mut RenderContext, scale: f32) -> Result<(), Box<Error>> {
+        let (font, char_width, line_height) = Resources::load_font(rx, &self.theme, scale)?;
+        self.font = font;
+        self.char_width = char_width;
+        self.line_height = line_height;
+        self.scale = scale;
+        Ok(())
+    }
+
+    /// A length in layout units converted to pixels
+    pub fn px(&self, v: f32) -> f32 {
+        v * self.scale
+    }
+}
+
+/// Draw one block of a flame graph, returns true if the mouse is over it
+pub fn paint_block(rx: &mut RenderContext, res: &Resources, r: Rect, (fr, fg, fb): Rgb, method_id: u32, data: &VizData, mouse: Point) -> bool {
+    rx.set_color(Color::rgb(fr, fg, fb));
+    rx.fill_rect(r);
+    let hovered = r.contains(mouse);
+    if hovered {
+        rx.set_color(Color::rgb(fr * 0.75, fg * 0.33, fb));
+    } else {
+        rx.set_color(Color::rgb(fr * 0.25, fg * 0.66, fb));
+    }
+    rx.stroke_rect(r, res.px(2.0));
+    // only the part of the block that is on screen has room for a label
+    let x = r.x.max(0.0) + res.px(2.0);
+    let w = (r.x + r.w).min(rx.bounds().w) - x - res.px(2.0);
+    let max_chars = (w / res.char_width).floor().max(0.0) as usize;
+    if max_chars >= 2 {
+        // grouped frames have a plain name rather than a method signature
+        let label = match data.method_names.get(&method_id) {
+            Some(m) => Some(m.fit(max_chars)),
+            None => data.method_index.get(&method_id).map(|s| fit_str(s, max_chars))
+        };
+        if let Some(l) = label {
+            rx.set_color(to_color(res.theme.block_text));
+            rx.draw_text(Rect::xywh(x, r.y + res.px(2.0), w, r.h), &l, &res.font);
+        }
+    }
+    hovered
+}
+
+/// Tooltip text for a call, with how it compares to its parent, its thread and the method's other calls
+fn call_summary(data: &VizData, i: usize) -> String {
+    let cr = &data.calls[i];
+    let self_time = data.self_times.get(i).cloned().unwrap_or(0);
+    let mut s = format!("{}\nStart Time: {}\nElapsed Time: {} (self {})",
+                        data.method_index.get(&cr.method_id).unwrap_or(&String::from("?")),
+                        format_duration(cr.start_time), format_duration(cr.elapsed_time), format_duration(self_time));
+    if let Some(p) = data.parents.get(i).cloned().unwrap_or(None) {
+        s.push_str(&format!("\n{:.1}% of parent", cr.elapsed_time as f64 / data.calls[p].elapsed_time.max(1) as f64 * 100.0));
+    }
+    if let Some(&busy) = data.thread_busy_time.get(&cr.thread_id) {
+        s.push_str(&format!("\n{:.1}% of thread total", cr.elapsed_time as f64 / busy.max(1) as f64 * 100.0));
+    }
+    if let Some(stats) = data.method_stats.get(&cr.method_id) {
+        let n = data.invocation_index.get(i).cloned().unwrap_or(0) + 1;
+        let ratio = cr.elapsed_time as f64 / stats.median_time.max(1) as f64;
+        s.push_str(&format!("\ninvocation {} of {}, {}", n, stats.count,
+                            if stats.count < 2 { String::from("only call") }
+                            else if ratio >= 1.05 { format!("{:.1}x slower than median", ratio) }
+                            else if ratio <= 0.95 { format!("{:.1}x faster than median", 1.0 / ratio.max(1e-9)) }
+                            else { String::from("about the median") }));
+    }
+    s.push_str(&format!("\n{}, Depth {}\nid {}", thread_label(data, cr.thread_id), cr.depth, data.method_key(cr.method_id)));
+    let recursion = data.recursion_depth.get(i).cloned().unwrap_or(0);
+    if recursion > 0 {
+        s.push_str(&format!("\n{} recursive calls folded", recursion));
+    }
+    s
+}
+
+pub fn paint_tooltip(rx: &mut RenderContext, res: &Resources, text: &str, mouse: Point, bounds: Rect) {
+    let tx = rx.new_text_layout(text, &res.font, bounds.w, bounds.h).expect("create tooltip layout");
+    rx.set_color(to_color(res.theme.tooltip_background));
+    let mut ttb = tx.bounds().offset(mouse).offset(Point::xy(res.px(16.0), 0.0));
+    ttb.w += res.px(8.0); ttb.h += res.px(8.0);
+    rx.fill_rect(ttb);
+    rx.set_color(to_color(res.theme.tooltip_text));
+    rx.draw_text_layout(Point::xy(res.px(4.0) + ttb.x, res.px(4.0) + ttb.y), &tx);
+    rx.set_color(to_color(res.theme.tooltip_border));
+    rx.stroke_rect(ttb, res.px(2.0));
 }
 
 pub trait VizView {
     fn event(&mut self, e: &WindowEvent, data: &VizData, menus: &mut MenuContext) -> bool;
     fn menu_selection(&mut self, data: &VizData, tag: &'static str, sel: usize);
+    fn prompt_result(&mut self, data: &VizData, tag: &'static str, text: &str, menus: &mut MenuContext);
+    fn filter_source(&self) -> Option<&str>;
     fn reset(&mut self);
 
+    /// Reset for a newly opened profile, forgetting the threads and filter picked for the old one as well
+    fn reset_for_new_file(&mut self) { self.reset() }
+
     fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData);
 
     fn status(&self, data: &VizData) -> String;
+
+    /// A view to switch to, replacing this one until the user goes back
+    fn next_view(&mut self) -> Option<Box<VizView>> { None }
+
+    /// Switch between scrolling through deep stacks and shrinking rows so every level fits on screen
+    fn toggle_fit_depth(&mut self) {}
+
+    /// Show or hide the panel for picking threads
+    fn toggle_thread_panel(&mut self) {}
+
+    /// Show or hide the list of the slowest calls
+    fn toggle_slowest_calls(&mut self) {}
+
+    /// A filter to go back to the previous view with once something has been picked in this one. It is
+    /// combined with the previous view's own filter
+    fn back_with_filter(&mut self) -> Option<String> { None }
+
+    /// Problems found by the view since the last call, to show to the user
+    fn take_errors(&mut self) -> Vec<String> { Vec::new() }
 }
 
 pub struct FlameChart {
-    current_thread_id: usize,
+    /// Threads to show, overlaid on each other. Empty shows every thread
+    threads: Vec<u32>,
+    thread_panel: ThreadPanel,
     offset_x: i64,
     pixels_per_nanosecond: f32,
     last_mouse: Point,
-    mouse_state: Option<(MouseButton, Point, i64)>,
+    mouse_state: Option<(MouseButton, Point, i64, f32)>,
     bounds: Rect,
+    /// Distance scrolled down from the first row, in pixels
+    offset_y: f32,
+    /// Where the scrollbar thumb was grabbed and the offset at the time
+    scrollbar_drag: Option<(f32, f32)>,
+    /// Call to bring on screen once the next paint knows the row layout
+    scroll_to: Option<usize>,
+    /// Shrink rows so the deepest call fits instead of scrolling
+    fit_depth: bool,
+    /// Row layout from the last paint, rows start below the status bar and minimap
+    chart_top: f32,
+    row_stride: f32,
+    max_row: u32,
+    minimap: Minimap,
+    minimap_bounds: Rect,
+    /// The viewport in the minimap is being dragged
+    minimap_drag: bool,
+    /// Pool whose threads are exactly the ones shown and where its utilization lane is drawn
+    pool_lane: Option<(usize, Rect)>,
     selected_index: isize,
+    filter: Option<(String, Filter)>,
+    /// Whether each call matches the filter, worked out once for the call count and load state in `matching_for`
+    matching: Vec<bool>,
+    matching_for: Option<(usize, bool)>,
+    /// Call whose subtree is the only thing drawn
+    focus: Option<usize>,
+    breadcrumbs: Vec<(Rect, Option<usize>)>,
+    next_view: Option<Box<VizView>>,
+    /// Highlight calls matching the filter instead of hiding the rest
+    highlight_matches: bool,
+    /// The inspect key is held, show every thread's stack at the time under the mouse
+    inspecting: bool,
+    /// Call picked with a left click, its stack is shown in the call stack pane
+    selection: Option<usize>,
+    stack_pane: CallStackPane,
+    /// Which calls stepping to the next or previous invocation of the selected call's method goes through
+    invocation_scope: InvocationScope,
+    invocations: InvocationCursor,
+    slowest: SlowestList,
+    /// Hidpi factor from the last paint, so clicks can be hit tested against the same layout
+    scale: f32,
 }
 
 impl FlameChart {
     pub fn init(rx: &mut RenderContext) -> FlameChart {
         FlameChart {
-            current_thread_id: 0,
+            threads: Vec::new(),
+            thread_panel: ThreadPanel::new(),
             offset_x: 0,
             pixels_per_nanosecond: 0.00005,
             last_mouse: Point::xy(0.0, 0.0), mouse_state: None,
             bounds: rx.bounds(),
+            offset_y: 0.0,
+            scrollbar_drag: None,
+            scroll_to: None,
+            fit_depth: false,
+            chart_top: ROW_STRIDE,
+            row_stride: ROW_STRIDE,
+            max_row: 0,
+            minimap: Minimap::new(),
+            minimap_bounds: Rect::xywh(0.0, 0.0, 0.0, 0.0),
+            minimap_drag: false,
+            pool_lane: None,
             selected_index: -1,
+            filter: None,
+            matching: Vec::new(),
+            matching_for: None,
+            focus: None,
+            breadcrumbs: Vec::new(),
+            next_view: None,
+            highlight_matches: false,
+            inspecting: false,
+            selection: None,
+            stack_pane: CallStackPane::new(),
+            invocation_scope: InvocationScope::AllThreads,
+            invocations: InvocationCursor::new(),
+            slowest: SlowestList::new(),
+            scale: 1.0,
+        }
+    }
+
+    /// Threads that are drawn, only the focused call's thread while focused
+    fn visible_threads(&self, data: &VizData) -> Vec<u32> {
+        match self.focus {
+            Some(f) => vec![data.calls[f].thread_id],
+            None => self.threads.clone()
+        }
+    }
+
+    /// Check if a call should be drawn given the visible threads, focus and filter
+    fn shown(&self, i: usize, cr: &CallRecord, data: &VizData, threads: &[u32]) -> bool {
+        (threads.is_empty() || threads.contains(&cr.thread_id)) &&
+            self.focus.map(|f| data.is_within(i, f)).unwrap_or(true) &&
+            (self.highlight_matches || self.matches(i, cr, data))
+    }
+
+    fn matches(&self, i: usize, cr: &CallRecord, data: &VizData) -> bool {
+        match self.filter {
+            Some((_, ref f)) => match self.matching_for {
+                Some(_) if i < self.matching.len() => self.matching[i],
+                _ => f.matches(cr, data)
+            },
+            None => true
+        }
+    }
+
+    fn set_filter(&mut self, data: &VizData, filter: Option<(String, Filter)>) {
+        self.filter = filter;
+        self.matching_for = None;
+        self.update_matching(data);
+    }
+
+    /// Run the filter over every call again if it or the data changed since the last time
+    fn update_matching(&mut self, data: &VizData) {
+        let key = (data.calls.len(), data.loaded);
+        if self.matching_for == Some(key) { return; }
+        self.matching = match self.filter {
+            Some((_, ref f)) => data.calls.iter().map(|cr| f.matches(cr, data)).collect(),
+            None => Vec::new()
+        };
+        self.matching_for = Some(key);
+    }
+
+    /// Row a call is drawn in. When focused, the focused call sits in the first row below the breadcrumbs
+    fn row(&self, cr: &CallRecord, data: &VizData) -> u32 {
+        match self.focus {
+            Some(f) => cr.depth - data.calls[f].depth + 2,
+            None => cr.depth
+        }
+    }
+
+    /// The call drawn under a point, if any
+    fn call_at(&self, data: &VizData, p: Point) -> Option<usize> {
+        let threads = self.visible_threads(data);
+        for (i,cr) in data.calls.iter().enumerate() {
+            if !self.shown(i, cr, data, &threads) { continue; }
+            let r = self.call_rect(cr, data);
+            if r.w < 2.0 { continue; }
+            if r.x+r.w < 0.0 || r.x > self.bounds.w { continue; }
+            if r.contains(p) { return Some(i); }
+        }
+        None
+    }
+
+    /// Select a call, zoom to it and bring it on screen, switching to its thread if that isn't shown
+    fn select(&mut self, data: &VizData, call: usize) {
+        if self.focus.map(|f| !data.is_within(call, f)).unwrap_or(false) {
+            self.set_focus(data, None);
+        }
+        let cr = data.calls[call];
+        if !self.threads.is_empty() && !self.threads.contains(&cr.thread_id) {
+            self.threads = vec![cr.thread_id];
+        }
+        self.zoom_to(&cr);
+        self.selection = Some(call);
+        self.scroll_to = Some(call);
+        self.update_invocations(data);
+    }
+
+    /// Select the next or previous invocation of the selected call's method
+    fn step_invocation(&mut self, data: &VizData, forward: bool) {
+        let sel = match self.selection { Some(s) => s, None => return };
+        if let Some(c) = self.invocations.step(data, sel, self.invocation_scope, forward) {
+            self.select(data, c);
+        }
+    }
+
+    /// Gather the selected call's invocations for the status bar when the selection or scope has changed
+    fn update_invocations(&mut self, data: &VizData) {
+        match self.selection {
+            Some(s) if s < data.calls.len() => self.invocations.update(data, s, self.invocation_scope),
+            _ => {}
+        }
+    }
+
+    /// Make sure every call matching the filter can be seen by showing their threads and leaving a focus that
+    /// holds none of them
+    fn show_matching_threads(&mut self, data: &VizData) {
+        let matching: Vec<usize> = (0..data.calls.len()).filter(|&i| self.matches(i, &data.calls[i], data)).collect();
+        if let Some(f) = self.focus {
+            if !matching.iter().any(|&i| data.is_within(i, f)) {
+                self.set_focus(data, None);
+            }
+        }
+        if self.threads.is_empty() { return; }
+        let mut threads: Vec<u32> = matching.iter().map(|&i| data.calls[i].thread_id).collect();
+        threads.sort();
+        threads.dedup();
+        self.threads = threads;
+    }
+
+    /// Top of a row before scrolling
+    fn row_y(&self, row: u32) -> f32 {
+        self.chart_top + self.row_stride * (row as f32 - 1.0)
+    }
+
+    fn call_rect(&self, cr: &CallRecord, data: &VizData) -> Rect {
+        let x = (-self.offset_x + (cr.start_time) as i64) as f32 * self.pixels_per_nanosecond;
+        let w = cr.elapsed_time as f32 * self.pixels_per_nanosecond;
+        Rect::xywh(x, self.row_y(self.row(cr, data)) - self.offset_y, w, self.row_stride * (ROW_HEIGHT / ROW_STRIDE))
+    }
+
+    /// Height of every row down to the deepest visible call
+    fn content_height(&self) -> f32 {
+        self.row_y(self.max_row + 1)
+    }
+
+    /// Center the time axis on a point in time picked in the minimap
+    fn center_on(&mut self, time: u64) {
+        let visible = (self.bounds.w / self.pixels_per_nanosecond) as i64;
+        self.offset_x = (time as i64 - visible / 2).max(0);
+    }
+
+    fn clamp_offset_y(&mut self) {
+        self.offset_y = self.offset_y.min(self.content_height() - self.bounds.h).max(0.0);
+    }
+
+    /// Scrollbar track and thumb along the right edge, if there is anything to scroll
+    fn scrollbar_rects(&self) -> Option<(Rect, Rect)> {
+        let content = self.content_height();
+        if content <= self.bounds.h { return None; }
+        let w = 10.0 * self.scale;
+        let track = Rect::xywh(self.bounds.w - w, 0.0, w, self.bounds.h);
+        let thumb = Rect::xywh(track.x, self.offset_y / content * self.bounds.h, w, (self.bounds.h * self.bounds.h / content).max(w));
+        Some((track, thumb))
+    }
+
+    fn zoom_to(&mut self, cr: &CallRecord) {
+        self.pixels_per_nanosecond = (self.bounds.w / cr.elapsed_time as f32) * 0.9;
+        self.offset_x = cr.start_time as i64 - (self.bounds.w * 0.05 / self.pixels_per_nanosecond) as i64;
+    }
+
+    fn set_focus(&mut self, data: &VizData, focus: Option<usize>) {
+        match focus {
+            Some(f) => self.zoom_to(&data.calls[f]),
+            None => self.reset()
+        }
+        self.offset_y = 0.0;
+        self.focus = focus;
+        self.breadcrumbs.clear();
+    }
+
+    fn paint_breadcrumbs(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData) {
+        self.breadcrumbs.clear();
+        let f = match self.focus { Some(f) => f, None => return };
+        let mut crumbs = vec![(String::from("all"), None)];
+        for a in data.ancestors(f).into_iter().chain(Some(f)) {
+            crumbs.push((data.method_index.get(&data.calls[a].method_id).cloned().unwrap_or(String::from("?")), Some(a)));
+        }
+        let mut x = res.px(2.0);
+        for (i, (name, target)) in crumbs.into_iter().enumerate() {
+            let tx = rx.new_text_layout(&if i == 0 { name } else { format!("> {}", name) },
+                                        &res.font, self.bounds.w, res.px(ROW_HEIGHT)).expect("create breadcrumb layout");
+            let r = Rect::xywh(x, self.chart_top + res.px(2.0), tx.bounds().w + res.px(8.0), tx.bounds().h + res.px(4.0));
+            rx.set_color(to_color(if r.contains(self.last_mouse) { res.theme.panel_highlight } else { res.theme.panel_background }));
+            rx.fill_rect(r);
+            rx.set_color(to_color(res.theme.panel_text));
+            rx.draw_text_layout(Point::xy(r.x + res.px(4.0), r.y + res.px(2.0)), &tx);
+            x += r.w + res.px(2.0);
+            self.breadcrumbs.push((r, target));
         }
     }
 }
 
 impl VizView for FlameChart {
     fn status(&self, data: &VizData) -> String {
-        let current_thread_ix = if self.current_thread_id == 0 { 0 } else { data.thread_ids[self.current_thread_id-1] };
-        format!("{}, {:.2}% | Thread #{}", self.offset_x, ((self.offset_x) as f64 / data.abs_end_time as f64)*100.0,
-                current_thread_ix)
+        let threads = self.visible_threads(data);
+        format!("{}, {:.2}% | {}{}{}{}", self.offset_x, ((self.offset_x) as f64 / data.abs_end_time as f64)*100.0,
+                match threads.len() {
+                    0 => String::from("all threads"),
+                    1 => thread_label(data, threads[0]),
+                    n => format!("{} threads", n)
+                },
+                if self.focus.is_some() { " | focused" } else { "" },
+                match self.selection.and_then(|s| self.invocations.position(data, s, self.invocation_scope)) {
+                    Some((n, total)) => format!(" | invocation {} / {} ({})", n + 1, total, self.invocation_scope.describe()),
+                    None => String::new()
+                },
+                self.filter.as_ref().map(|&(ref src, _)| format!(" | filter: {}", src)).unwrap_or_default())
     }
 
     fn reset(&mut self) {
         self.offset_x = 0;
+        self.offset_y = 0.0;
         self.pixels_per_nanosecond = 0.0;
+        self.focus = None;
+        self.breadcrumbs.clear();
+        self.selected_index = -1;
+        self.selection = None;
+        self.scroll_to = None;
+    }
+
+    fn reset_for_new_file(&mut self) {
+        self.reset();
+        self.threads.clear();
+        self.thread_panel.name_filter.clear();
+        self.filter = None;
+        self.matching.clear();
+        self.matching_for = None;
     }
 
     fn event(&mut self, e: &WindowEvent, data: &VizData, menus: &mut MenuContext) -> bool {
@@ -82,60 +522,212 @@ impl VizView for FlameChart {
                         //self.pixels_per_nanosecond += 0.000001;
                         self.pixels_per_nanosecond /= 0.9;
                     },
-                    Some(VirtualKeyCode::PageUp) => {
+                    Some(VirtualKeyCode::PageUp) | Some(VirtualKeyCode::PageDown) => {
+                        // step through single threads, with all threads before the first one
+                        if k.state == ElementState::Released && self.focus.is_none() {
+                            let current = match self.threads.len() {
+                                1 => data.thread_ids.iter().position(|&t| t == self.threads[0]).map(|p| p + 1).unwrap_or(0),
+                                _ => 0
+                            };
+                            let next = if k.virtual_keycode == Some(VirtualKeyCode::PageUp) {
+                                (current + 1).min(data.thread_ids.len())
+                            } else {
+                                current.saturating_sub(1)
+                            };
+                            self.threads = if next == 0 { Vec::new() } else { vec![data.thread_ids[next - 1]] };
+                        }
+                    },
+                    Some(VirtualKeyCode::Escape) if self.selection.is_some() => {
+                        if k.state == ElementState::Released { self.selection = None; }
+                        return true;
+                    },
+                    Some(VirtualKeyCode::I) => {
+                        self.inspecting = k.state == ElementState::Pressed;
+                    },
+                    Some(VirtualKeyCode::T) => {
                         if k.state == ElementState::Released {
-                            if self.current_thread_id < data.thread_ids.len() {
-                                self.current_thread_id += 1;
-                            }
+                            self.toggle_thread_panel();
+                        }
+                    },
+                    Some(VirtualKeyCode::F) => {
+                        if k.state == ElementState::Released {
+                            self.toggle_fit_depth();
+                        }
+                    },
+                    Some(VirtualKeyCode::N) | Some(VirtualKeyCode::P) => {
+                        if k.state == ElementState::Released {
+                            let forward = k.virtual_keycode == Some(VirtualKeyCode::N);
+                            self.step_invocation(data, forward);
                         }
                     },
-                    Some(VirtualKeyCode::PageDown) => {
+                    Some(VirtualKeyCode::S) => {
+                        // cycle which invocations N and P step through
                         if k.state == ElementState::Released {
-                            self.current_thread_id = self.current_thread_id.saturating_sub(1);
+                            self.invocation_scope = self.invocation_scope.next();
+                            self.update_invocations(data);
                         }
                     }
                     _ => {}
                 }
             },
             &WindowEvent::CursorMoved { position: (x,y), .. } => {
-                if let Some((MouseButton::Left, click_pos, click_offset)) = self.mouse_state {
+                if self.minimap_drag {
+                    let t = self.minimap.time_at(self.minimap_bounds, x as f32, data);
+                    self.center_on(t);
+                } else if let Some((click_y, click_offset_y)) = self.scrollbar_drag {
+                    self.offset_y = click_offset_y + (y as f32 - click_y) * self.content_height() / self.bounds.h;
+                    self.clamp_offset_y();
+                } else if let Some((MouseButton::Left, click_pos, click_offset, click_offset_y)) = self.mouse_state {
                     self.offset_x = ((click_pos.x - self.last_mouse.x) / self.pixels_per_nanosecond) as i64 + click_offset;
+                    self.offset_y = click_pos.y - self.last_mouse.y + click_offset_y;
+                    self.clamp_offset_y();
                 }
                 self.last_mouse = Point::xy(x as f32, y as f32);
             },
-            &WindowEvent::MouseInput{ state, button, .. } => {
+            &WindowEvent::MouseInput{ state, button, modifiers, .. } => {
+                if self.thread_panel.contains(self.last_mouse) {
+                    if state == ElementState::Released && button == MouseButton::Left {
+                        let before = self.threads.clone();
+                        match self.thread_panel.click(self.last_mouse) {
+                            PanelClick::AllThreads => self.threads.clear(),
+                            // with control held threads are added to or removed from the overlay
+                            PanelClick::Thread(t) => if modifiers.ctrl {
+                                match self.threads.iter().position(|&s| s == t) {
+                                    Some(p) => { self.threads.remove(p); },
+                                    None => self.threads.push(t)
+                                }
+                            } else {
+                                self.threads = vec![t];
+                            },
+                            PanelClick::Pool(threads) => if modifiers.ctrl {
+                                for t in threads {
+                                    if !self.threads.contains(&t) { self.threads.push(t); }
+                                }
+                            } else {
+                                self.threads = threads;
+                            },
+                            PanelClick::EditFilter => menus.prompt("thread filter: ", &self.thread_panel.name_filter.clone(), "threads"),
+                            PanelClick::EditPools => menus.prompt("add pool (<thread name regex> => <pool name>) or clear: ", "", "pools"),
+                            PanelClick::Nothing => {}
+                        }
+                        // leave the focused call only once its thread is no longer picked
+                        if let Some(f) = self.focus {
+                            if self.threads != before && !self.threads.is_empty() && !self.threads.contains(&data.calls[f].thread_id) {
+                                self.set_focus(data, None);
+                            }
+                        }
+                    }
+                    self.mouse_state = None;
+                    return true;
+                }
+                if self.slowest.contains(self.last_mouse) {
+                    if state == ElementState::Released && button == MouseButton::Left {
+                        if let Some(c) = self.slowest.click(self.last_mouse) {
+                            self.threads = vec![data.calls[c].thread_id];
+                            self.select(data, c);
+                        }
+                    }
+                    self.mouse_state = None;
+                    return true;
+                }
+                if self.stack_pane.contains(self.last_mouse) {
+                    if state == ElementState::Released && button == MouseButton::Left {
+                        if let Some(c) = self.stack_pane.click(self.last_mouse) {
+                            self.select(data, c);
+                        }
+                    }
+                    self.mouse_state = None;
+                    return true;
+                }
+                if state == ElementState::Pressed && button == MouseButton::Left {
+                    if let Some((track, thumb)) = self.scrollbar_rects() {
+                        if track.contains(self.last_mouse) {
+                            if !thumb.contains(self.last_mouse) {
+                                // jump so the thumb is centered under the mouse, then drag from there
+                                self.offset_y = (self.last_mouse.y - thumb.h / 2.0) / self.bounds.h * self.content_height();
+                                self.clamp_offset_y();
+                            }
+                            self.scrollbar_drag = Some((self.last_mouse.y, self.offset_y));
+                            return true;
+                        }
+                    }
+                }
+                if state == ElementState::Pressed && button == MouseButton::Left && self.minimap_bounds.contains(self.last_mouse) {
+                    // jump to the clicked time and keep following the mouse until released
+                    let t = self.minimap.time_at(self.minimap_bounds, self.last_mouse.x, data);
+                    self.center_on(t);
+                    self.minimap_drag = true;
+                    return true;
+                }
+                if state == ElementState::Released && (self.scrollbar_drag.take().is_some() || self.minimap_drag) {
+                    self.minimap_drag = false;
+                    return true;
+                }
+                let pressed = self.mouse_state;
                 self.mouse_state = match state {
                     ElementState::Pressed =>
-                        Some((button, self.last_mouse, self.offset_x)),
+                        Some((button, self.last_mouse, self.offset_x, self.offset_y)),
                     _ => None
                 };
+                if state == ElementState::Released && button == MouseButton::Left {
+                    let hit = self.breadcrumbs.iter().find(|&&(r, _)| r.contains(self.last_mouse)).map(|&(_, t)| t);
+                    if let Some(target) = hit {
+                        self.set_focus(data, target);
+                        return true;
+                    }
+                    // a click that didn't drag the chart selects the call under the mouse
+                    if let Some((MouseButton::Left, p, _, _)) = pressed {
+                        if (p.x - self.last_mouse.x).abs() < 4.0 * self.scale && (p.y - self.last_mouse.y).abs() < 4.0 * self.scale {
+                            let hit = self.call_at(data, self.last_mouse);
+                            if hit.is_some() {
+                                self.selection = hit;
+                                self.update_invocations(data);
+                                return true;
+                            }
+                        }
+                    }
+                }
                 if state == ElementState::Released && button == MouseButton::Right {
-                    let current_thread_ix = if self.current_thread_id == 0 { 0 } else { data.thread_ids[self.current_thread_id-1] };
                     self.selected_index = -1;
-                    for (i,cr) in data.calls.iter().enumerate() {
-                        if current_thread_ix > 0 && cr.thread_id != current_thread_ix { continue; }
-                        let w = cr.elapsed_time as f32 * self.pixels_per_nanosecond;
-                        if w < 2.0 { continue; }
-                        let x = (-self.offset_x + (cr.start_time) as i64) as f32 * self.pixels_per_nanosecond;
-                        if x+w < 0.0 || x > self.bounds.w { continue; }
-
-                        if Rect::xywh(x, 34.0*cr.depth as f32, w, 32.0).contains(self.last_mouse) {
-                            menus.popup(vec![ "zoom into view" ], self.last_mouse, "call");
-                            self.selected_index = i as isize;
-                            return true;
-                        }
+                    if let Some(i) = self.call_at(data, self.last_mouse) {
+                        menus.popup(vec![ "zoom into view", "focus on call",
+                                          if data.loaded { "focus on method" } else { "focus on method (still loading)" },
+                                          "previous invocation", "next invocation", "slowest calls of method", "duration distribution" ], self.last_mouse, "call");
+                        self.selected_index = i as isize;
+                        return true;
                     }
                 }
             },
-            &WindowEvent::MouseWheel { delta, .. } => {
+            &WindowEvent::MouseWheel { delta, modifiers, .. } => {
+                if self.thread_panel.contains(self.last_mouse) {
+                    match delta {
+                        MouseScrollDelta::LineDelta(_, y) => self.thread_panel.scroll_by(-y.signum() as isize),
+                        MouseScrollDelta::PixelDelta(_, y) => self.thread_panel.scroll_by(-y.signum() as isize)
+                    }
+                    return true;
+                }
+                if self.slowest.contains(self.last_mouse) {
+                    match delta {
+                        MouseScrollDelta::LineDelta(_, y) => self.slowest.scroll_by(-y.signum() as isize),
+                        MouseScrollDelta::PixelDelta(_, y) => self.slowest.scroll_by(-y.signum() as isize)
+                    }
+                    return true;
+                }
+                // the wheel zooms, with shift held or over the scrollbar it scrolls through the stack
+                let scroll = modifiers.shift || self.scrollbar_rects().map(|(track, _)| track.contains(self.last_mouse)).unwrap_or(false);
                 match delta {
-                    MouseScrollDelta::LineDelta(_, y) => {
+                    MouseScrollDelta::LineDelta(_, y) => if scroll {
+                        self.offset_y -= y * self.row_stride;
+                    } else {
                         self.pixels_per_nanosecond += y * 0.00001;
                     },
-                    MouseScrollDelta::PixelDelta(x, y) => {
+                    MouseScrollDelta::PixelDelta(_, y) => if scroll {
+                        self.offset_y -= y;
+                    } else {
                         self.pixels_per_nanosecond += y * 0.0001;
                     }
                 }
+                self.clamp_offset_y();
             },
             _ => {}
 
@@ -147,67 +739,232 @@ impl VizView for FlameChart {
         if tag == "call" {
             match sel {
                 0 => {
-                    let cr = &data.calls[self.selected_index as usize];
-                    let (new_offset, new_ppn) = (cr.start_time as i64, self.bounds.w / cr.elapsed_time as f32);
-                    self.pixels_per_nanosecond = (self.bounds.w / cr.elapsed_time as f32) * 0.9;
-                    self.offset_x = cr.start_time as i64 - (self.bounds.w * 0.05 / self.pixels_per_nanosecond) as i64;
-                }
+                    let cr = data.calls[self.selected_index as usize];
+                    self.zoom_to(&cr);
+                    self.scroll_to = Some(self.selected_index as usize);
+                },
+                1 => {
+                    let i = self.selected_index as usize;
+                    self.set_focus(data, Some(i));
+                },
+                // merging needs the whole call tree
+                2 if data.loaded => {
+                    let method_id = data.calls[self.selected_index as usize].method_id;
+                    self.next_view = Some(Box::new(MergedFlameGraph::new(data, method_id, self.bounds)));
+                },
+                2 => {},
+                3 | 4 => {
+                    self.selection = Some(self.selected_index as usize);
+                    self.step_invocation(data, sel == 4);
+                },
+                5 => {
+                    self.slowest.show(Some(data.calls[self.selected_index as usize].method_id));
+                },
+                6 => {
+                    let method_id = data.calls[self.selected_index as usize].method_id;
+                    self.next_view = Some(Box::new(DistributionView::new(data, method_id)));
+                },
                 _ => unreachable!()
             }
         }
     }
 
+    fn prompt_result(&mut self, data: &VizData, tag: &'static str, text: &str, menus: &mut MenuContext) {
+        if tag == "filter" || tag == "selection" {
+            if text.trim().len() == 0 {
+                self.set_filter(data, None);
+                return;
+            }
+            // calls picked in another view narrow down the filter that is already there instead of replacing it
+            let src = match (tag, self.filter.as_ref()) {
+                ("selection", Some(&(ref current, _))) => format!("({}) and ({})", current, text),
+                _ => String::from(text)
+            };
+            match Filter::parse(&src) {
+                Ok(f) => {
+                    self.set_filter(data, Some((src, f)));
+                    if tag == "selection" {
+                        self.show_matching_threads(data);
+                    }
+                    // bring the first match on screen in case it is below the fold
+                    let threads = self.visible_threads(data);
+                    let first = data.calls.iter().enumerate()
+                        .filter(|&(i, cr)| self.shown(i, cr, data, &threads) && self.matches(i, cr, data))
+                        .min_by_key(|&(_, cr)| cr.depth).map(|(i, _)| i);
+                    self.scroll_to = first;
+                },
+                Err(e) => menus.popup(vec![&e.to_string()], self.last_mouse, "err")
+            }
+        } else if tag == "threads" {
+            self.thread_panel.name_filter = String::from(text.trim());
+        } else if tag == "pools" {
+            if let Err(e) = self.thread_panel.add_pool_rule(data, text) {
+                menus.popup(vec![&e], self.last_mouse, "err");
+            }
+        }
+    }
+
+    /// Source of the current filter expression, used to pre-fill the filter prompt
+    fn filter_source(&self) -> Option<&str> {
+        self.filter.as_ref().map(|&(ref src, _)| src.as_str())
+    }
+
+    fn next_view(&mut self) -> Option<Box<VizView>> {
+        self.next_view.take()
+    }
+
+    fn take_errors(&mut self) -> Vec<String> {
+        self.thread_panel.take_errors()
+    }
+
+    fn toggle_fit_depth(&mut self) {
+        self.fit_depth = !self.fit_depth;
+        self.offset_y = 0.0;
+    }
+
+    fn toggle_thread_panel(&mut self) {
+        self.thread_panel.visible = !self.thread_panel.visible;
+    }
+
+    fn toggle_slowest_calls(&mut self) {
+        if self.slowest.visible {
+            self.slowest.visible = false;
+        } else {
+            self.slowest.show(None);
+        }
+    }
+
     fn paint(&mut self, rx: &mut RenderContext, res: &Resources, data: &VizData) {
         self.pixels_per_nanosecond = self.pixels_per_nanosecond.max(0.000001);
         self.offset_x = self.offset_x.max(0);
         self.bounds = rx.bounds();
-        let current_thread_ix = if self.current_thread_id == 0 { 0 } else { data.thread_ids[self.current_thread_id-1] };
+        self.scale = res.scale;
+        self.update_invocations(data);
+        self.update_matching(data);
+        if self.focus.map(|f| f >= data.calls.len() || !data.loaded).unwrap_or(false) {
+            self.focus = None;
+        }
+        if let Some(f) = self.focus {
+            // keep the time axis inside the focused call
+            let fc = data.calls[f];
+            self.pixels_per_nanosecond = self.pixels_per_nanosecond.max(self.bounds.w / fc.elapsed_time.max(1) as f32 * 0.9);
+            let visible = (self.bounds.w / self.pixels_per_nanosecond) as i64;
+            let min_offset = fc.start_time as i64 - visible / 20;
+            let max_offset = ((fc.start_time + fc.elapsed_time) as i64 + visible / 20 - visible).max(min_offset);
+            self.offset_x = self.offset_x.max(min_offset).min(max_offset);
+        }
+        let threads = self.visible_threads(data);
+        self.highlight_matches = res.color_scheme == ColorScheme::SearchMatch;
 
-        let mut hovered_record: Option<&CallRecord> = None;
+        // lay out rows for the deepest call that is shown before drawing anything
+        self.minimap_bounds = Rect::xywh(0.0, res.line_height + res.px(2.0), self.bounds.w, res.px(MINIMAP_HEIGHT));
+        self.chart_top = self.minimap_bounds.y + self.minimap_bounds.h + res.px(2.0);
+        // showing a whole pool adds a lane with how busy the pool was as a whole
+        self.pool_lane = match self.thread_panel.pool_for(data, &threads) {
+            Some(p) => {
+                let lane = Rect::xywh(0.0, self.chart_top, self.bounds.w, res.line_height + res.px(4.0));
+                self.chart_top += lane.h + res.px(2.0);
+                Some((p, lane))
+            },
+            None => None
+        };
+        let shown: Vec<usize> = (0..data.calls.len()).filter(|&i| self.shown(i, &data.calls[i], data, &threads)).collect();
+        self.max_row = shown.iter().map(|&i| self.row(&data.calls[i], data)).max().unwrap_or(0);
+        self.row_stride = res.px(ROW_STRIDE);
+        if self.fit_depth {
+            self.row_stride = ((self.bounds.h - self.chart_top) / self.max_row.max(1) as f32).min(self.row_stride).max(res.px(4.0));
+        }
+        if let Some(i) = self.scroll_to.take() {
+            if i < data.calls.len() {
+                let y = self.row_y(self.row(&data.calls[i], data));
+                if y < self.offset_y + self.chart_top || y + self.row_stride > self.offset_y + self.bounds.h {
+                    self.offset_y = y - self.bounds.h / 2.0;
+                }
+            }
+        }
+        self.clamp_offset_y();
 
-        for cr in data.calls.iter() {
-            if current_thread_ix > 0 && cr.thread_id != current_thread_ix { continue; }
-            let w = cr.elapsed_time as f32 * self.pixels_per_nanosecond;
-            if w < 2.0 { continue; }
-            let x = (-self.offset_x + (cr.start_time) as i64) as f32 * self.pixels_per_nanosecond;
-            if x+w < 0.0 || x > self.bounds.w { continue; }
+        let mut hovered_record: Option<usize> = None;
 
-            rx.set_color(Color::rgb(0.8, 0.6, (cr.method_id as f32 * 8.23).sin().abs()));
-            let r = Rect::xywh(x, 34.0*cr.depth as f32, w, 32.0);
-            rx.fill_rect(r);
-            if r.contains(self.last_mouse) {
-                hovered_record = Some(cr);
-                rx.set_color(Color::rgb(0.6, 0.2, (cr.method_id as f32 * 8.23).sin().abs()));
-            } else {
-                rx.set_color(Color::rgb(0.2, 0.4, (cr.method_id as f32 * 8.23).sin().abs()));
+        for &i in shown.iter() {
+            let cr = &data.calls[i];
+            let r = self.call_rect(cr, data);
+            if r.w < 2.0 { continue; }
+            if r.x+r.w < 0.0 || r.x > self.bounds.w { continue; }
+            // rows scrolled up past the top would cover the status bar and minimap
+            if r.y < self.chart_top - res.px(2.0) || r.y > self.bounds.h { continue; }
+
+            let fill = res.color_scheme.block_color(data, &res.theme, cr.method_id, Some(i), self.filter.is_some() && self.matches(i, cr, data));
+            if paint_block(rx, res, r, fill, cr.method_id, data, self.last_mouse) {
+                hovered_record = Some(i);
             }
-            rx.stroke_rect(r, 2.0);
-            if w > 128.0 {
-                rx.set_color(Color::rgb(0.0, 0.0, 0.0));
-                match data.method_index.get(&cr.method_id) {
-                    Some(m) => {
-                        let tr = Rect::xywh(r.x.max(0.0) + 2.0, r.y + 2.0, r.w, r.h);
-                        rx.draw_text(tr, m, &res.font)
-                    },
-                    None => {}
-                }
+            if self.selection == Some(i) {
+                rx.set_color(to_color(res.theme.text));
+                rx.stroke_rect(r, res.px(3.0));
+            }
+            let recursion = data.recursion_depth.get(i).cloned().unwrap_or(0);
+            if recursion > 0 && r.w > res.px(48.0) && r.h > res.px(26.0) {
+                let badge = format!("x{}", recursion + 1);
+                let bw = res.char_width * badge.len() as f32 + res.px(4.0);
+                let bx = (r.x + r.w).min(self.bounds.w) - bw - res.px(4.0);
+                let br = Rect::xywh(bx, r.y + res.px(6.0), bw, res.px(20.0));
+                rx.set_color(to_color(res.theme.panel_background));
+                rx.fill_rect(br);
+                rx.set_color(to_color(res.theme.panel_text));
+                rx.draw_text(Rect::xywh(br.x + res.px(2.0), br.y, br.w, br.h), &badge, &res.font);
             }
         }
 
+        self.paint_breadcrumbs(rx, res, data);
+
+        self.minimap.update(data, &threads);
+        let visible_start = self.offset_x as f32;
+        self.minimap.paint(rx, res, self.minimap_bounds, data, (visible_start, visible_start + self.bounds.w / self.pixels_per_nanosecond));
+        if self.minimap_bounds.contains(self.last_mouse) { hovered_record = None; }
+
+        if let Some((pool, lane)) = self.pool_lane {
+            let visible_end = self.offset_x as u64 + (self.bounds.w / self.pixels_per_nanosecond) as u64;
+            self.thread_panel.paint_pool_lane(rx, res, lane, pool, (self.offset_x as u64, visible_end));
+            if lane.contains(self.last_mouse) { hovered_record = None; }
+        }
+
+        if let Some((track, thumb)) = self.scrollbar_rects() {
+            rx.set_color(to_color(res.theme.panel_background));
+            rx.fill_rect(track);
+            rx.set_color(to_color(if self.scrollbar_drag.is_some() || thumb.contains(self.last_mouse) { res.theme.panel_border } else { res.theme.panel_highlight }));
+            rx.fill_rect(thumb);
+            if track.contains(self.last_mouse) { hovered_record = None; }
+        }
+
+        if self.selection.map(|s| s >= data.calls.len()).unwrap_or(false) {
+            self.selection = None;
+        }
+        let pane_w = self.bounds.w - res.px(12.0) - if self.thread_panel.visible { res.px(PANEL_WIDTH) } else { 0.0 };
+        self.stack_pane.paint(rx, res, data, self.selection, Rect::xywh(0.0, self.chart_top, pane_w, self.bounds.h - self.chart_top), self.last_mouse);
+        if self.stack_pane.contains(self.last_mouse) { hovered_record = None; }
+
+        let panel_area = Rect::xywh(0.0, self.chart_top, self.bounds.w - res.px(12.0), self.bounds.h - self.chart_top);
+        let visible_end = self.offset_x as u64 + (self.bounds.w / self.pixels_per_nanosecond) as u64;
+        self.thread_panel.paint(rx, res, data, panel_area, &threads, (self.offset_x as u64, visible_end), self.last_mouse);
+        if self.thread_panel.contains(self.last_mouse) { hovered_record = None; }
+
+        self.slowest.paint(rx, res, data, Rect::xywh(0.0, self.chart_top, pane_w, self.bounds.h - self.chart_top), self.selection, self.last_mouse);
+        if self.slowest.contains(self.last_mouse) { hovered_record = None; }
+
+        // legend in the corner of the chart left over by the panels
+        let chart_bottom = self.stack_pane.bounds().map(|b| b.y).unwrap_or(self.bounds.h);
+        res.color_scheme.paint_legend(rx, res, data, Rect::xywh(0.0, self.chart_top, pane_w, chart_bottom - self.chart_top));
+
+        if self.inspecting && self.last_mouse.y >= self.chart_top {
+            let t = (self.offset_x + (self.last_mouse.x / self.pixels_per_nanosecond) as i64).max(0) as u64;
+            paint_instant(rx, res, data, t, self.last_mouse.x,
+                          Rect::xywh(0.0, self.chart_top, self.bounds.w, self.bounds.h - self.chart_top));
+            hovered_record = None;
+        }
+
         // draw tooltip
-        if let Some(cr) = hovered_record {
-            let tx = rx.new_text_layout(&format!("{}\nStart Time: {}ns\nElapsed Time: {}ns\nThread #{}, Depth {}",
-                                                 data.method_index.get(&cr.method_id).unwrap_or(&String::from("?")),
-                                                 cr.start_time, cr.elapsed_time, cr.thread_id, cr.depth),
-                                        &res.font, self.bounds.w, self.bounds.h).expect("create tooltip layout");
-            rx.set_color(Color::rgb(0.3, 0.3, 0.3));
-            let mut ttb = tx.bounds().offset(self.last_mouse).offset(Point::xy(16.0, 0.0));
-            ttb.w += 8.0; ttb.h += 8.0;
-            rx.fill_rect(ttb);
-            rx.set_color(Color::rgb(0.8, 0.8, 0.8));
-            rx.draw_text_layout(Point::xy(4.0 + ttb.x, 4.0 + ttb.y), &tx);
-            rx.set_color(Color::rgb(0.6, 0.6, 0.6));
-            rx.stroke_rect(ttb, 2.0);
+        if let Some(i) = hovered_record {
+            paint_tooltip(rx, res, &call_summary(data, i), self.last_mouse, self.bounds);
         }
     }
 }